@@ -1,15 +1,520 @@
+mod schema;
+
+/// Number of crashes after which a file is no longer parsed, until it changes.
+const MAX_CRASHES: i64 = 3;
+
+/// Number of virtual machine instructions between checks of request cancellation.
+const CANCEL_CHECK_OPS: i32 = 1000;
+
+/// Max number of tags scanned for names containing a query as a subsequence.
+const MAX_SUBSEQUENCE_SCAN: usize = 50000;
+
 #[derive(Debug, Default, Clone)]
 pub struct FileInfo {
     /// The path of the file.
     pub path: std::path::PathBuf,
 
-    /// The modify time of the file.
+    /// The size of the file in bytes.
+    pub size: i64,
+
+    /// The modify time of the file, in nanoseconds since epoch.
     pub mtime: i64,
 
-    /// The parser time of the file.
+    /// The hash of the content.
+    ///
+    /// For scanned files, only computed when size or mtime changed. For records in
+    /// database, the hash of the content last parsed from disk.
+    pub hash: Option<i64>,
+
+    /// The `mtime` of the file when it was last parsed from disk, 0 if not parsed.
     pub ptime: i64,
 }
 
+/// The kind of a tag.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TagKind {
+    /// Function definition.
+    Function = 1,
+    /// Function prototype.
+    Prototype = 2,
+    /// Struct definition.
+    Struct = 3,
+    /// Union definition.
+    Union = 4,
+    /// Enum definition.
+    Enum = 5,
+    /// Type definition.
+    Typedef = 6,
+    /// Enumerator in enum.
+    Enumerator = 7,
+    /// Global variable.
+    Variable = 8,
+    /// Object-like macro.
+    Macro = 9,
+    /// Function-like macro.
+    FunctionMacro = 10,
+    /// Field of struct or union.
+    Field = 11,
+}
+
+impl std::convert::TryFrom<i64> for TagKind {
+    type Error = i64;
+
+    /// Convert from `i64` to `TagKind`.
+    ///
+    /// # Arguments
+    ///
+    /// + `value` - `i64` to convert.
+    ///
+    /// # Returns
+    ///
+    /// + `TagKind` converted from `i64`.
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(TagKind::Function),
+            2 => Ok(TagKind::Prototype),
+            3 => Ok(TagKind::Struct),
+            4 => Ok(TagKind::Union),
+            5 => Ok(TagKind::Enum),
+            6 => Ok(TagKind::Typedef),
+            7 => Ok(TagKind::Enumerator),
+            8 => Ok(TagKind::Variable),
+            9 => Ok(TagKind::Macro),
+            10 => Ok(TagKind::FunctionMacro),
+            11 => Ok(TagKind::Field),
+            unmatched => Err(unmatched),
+        }
+    }
+}
+
+/// The kind of a cross-reference.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum XrefKind {
+    /// Function call.
+    Call = 1,
+    /// Use of a type.
+    Type = 2,
+    /// Macro invocation.
+    Macro = 3,
+    /// Access to a field.
+    Field = 4,
+    /// Read of a variable.
+    Read = 5,
+    /// Write of a variable.
+    Write = 6,
+}
+
+impl std::convert::TryFrom<i64> for XrefKind {
+    type Error = i64;
+
+    /// Convert from `i64` to `XrefKind`.
+    ///
+    /// # Arguments
+    ///
+    /// + `value` - `i64` to convert.
+    ///
+    /// # Returns
+    ///
+    /// + `XrefKind` converted from `i64`.
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(XrefKind::Call),
+            2 => Ok(XrefKind::Type),
+            3 => Ok(XrefKind::Macro),
+            4 => Ok(XrefKind::Field),
+            5 => Ok(XrefKind::Read),
+            6 => Ok(XrefKind::Write),
+            unmatched => Err(unmatched),
+        }
+    }
+}
+
+/// A range in source file, rows and columns are zero based.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TagRange {
+    pub beg_row: i64,
+    pub beg_col: i64,
+    pub end_row: i64,
+    pub end_col: i64,
+}
+
+impl TagRange {
+    /// Check whether another range is inside this one.
+    ///
+    /// # Arguments
+    ///
+    /// + `other` - The other range.
+    pub fn contains(&self, other: &TagRange) -> bool {
+        (self.beg_row, self.beg_col) <= (other.beg_row, other.beg_col)
+            && (other.end_row, other.end_col) <= (self.end_row, self.end_col)
+    }
+}
+
+impl From<tree_sitter::Range> for TagRange {
+    fn from(value: tree_sitter::Range) -> Self {
+        TagRange {
+            beg_row: value.start_point.row as i64,
+            beg_col: value.start_point.column as i64,
+            end_row: value.end_point.row as i64,
+            end_col: value.end_point.column as i64,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TagInfo {
+    /// The ID of the tag, only valid for records read from database.
+    pub id: i64,
+
+    /// The kind of the tag.
+    pub kind: TagKind,
+
+    /// The range of the whole definition.
+    pub range: TagRange,
+
+    /// The range of the name.
+    pub name_range: TagRange,
+
+    /// The path of the file.
+    pub path: std::path::PathBuf,
+
+    /// The name of the tag.
+    pub name: String,
+
+    /// The enclosing tag.
+    ///
+    /// When inserting, this is the index of the enclosing tag in the same batch. When
+    /// reading from database, this is the ID of the enclosing tag.
+    pub scope: Option<i64>,
+
+    /// Extra information, e.g. the parameter list of function.
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct XrefInfo {
+    /// The ID of the xref, only valid for records read from database.
+    pub id: i64,
+
+    /// The kind of the reference.
+    pub kind: XrefKind,
+
+    /// The range of the identifier.
+    pub range: TagRange,
+
+    /// The path of the file.
+    pub path: std::path::PathBuf,
+
+    /// The referenced name.
+    pub name: String,
+
+    /// The tag that holds the reference.
+    ///
+    /// When inserting, this is the index of the holding tag in the same batch. When
+    /// reading from database, this is the ID of the holding tag.
+    pub hold: Option<i64>,
+}
+
+/// Tags and xrefs found in a file.
+#[derive(Debug, Default, Clone)]
+pub struct FileIndex {
+    /// The path of the file.
+    pub path: std::path::PathBuf,
+
+    /// The tags. `scope` is the index of enclosing tag in `tags`.
+    pub tags: Vec<TagInfo>,
+
+    /// The xrefs. `hold` is the index of holding tag in `tags`.
+    pub xrefs: Vec<XrefInfo>,
+
+    /// The hash of the parsed content, `None` if the content is not from disk.
+    pub hash: Option<i64>,
+}
+
+/// Condition that matches records of path `?1`, or of files in directory `?1`.
+const PATH_OR_CHILDREN: &str = "(path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/')";
+
+/// Run queries that stop early once the request is canceled.
+///
+/// # Arguments
+///
+/// + `conn` - The database connection.
+/// + `cancel` - Cancellation of the request.
+/// + `f` - The queries.
+fn interruptible<T, F>(
+    conn: &rusqlite::Connection,
+    cancel: &crate::request::CancelToken,
+    f: F,
+) -> rusqlite::Result<T>
+where
+    F: FnOnce() -> rusqlite::Result<T>,
+{
+    let token = cancel.clone();
+    conn.progress_handler(CANCEL_CHECK_OPS, Some(move || token.is_canceled()));
+    let ret = f();
+    conn.progress_handler(0, None::<fn() -> bool>);
+    ret
+}
+
+/// Header of a SQLite database file.
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+/// Check whether a file is created as a SQLite database, from its header.
+///
+/// # Arguments
+///
+/// + `path` - The path of the file.
+fn is_sqlite_file(path: &std::path::Path) -> bool {
+    use std::io::Read;
+
+    let mut header = [0; SQLITE_HEADER.len()];
+    std::fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .is_ok_and(|_| header == SQLITE_HEADER)
+}
+
+/// Find candidate tags for a fuzzy query, see `SqliteClient::search_tags`.
+fn search_tags(
+    conn: &rusqlite::Connection,
+    query: &str,
+    limit: usize,
+) -> rusqlite::Result<Vec<TagInfo>> {
+    let mut ret = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+
+    // Substring match from trigram index, which requires at least 3 characters.
+    if query.chars().count() >= 3 {
+        let sql = format!(
+            "SELECT {} FROM tags WHERE id IN (
+                SELECT rowid FROM tags_fts WHERE tags_fts MATCH ?1 LIMIT ?2
+            )",
+            TAG_COLUMNS
+        );
+        let pattern = format!("\"{}\"", query.replace('"', "\"\""));
+        let mut stmt = conn.prepare(&sql)?;
+        for tag in stmt.query_map(rusqlite::params![pattern, limit], tag_from_row)? {
+            let tag = tag?;
+            if seen.insert(tag.id) {
+                ret.push(tag);
+            }
+        }
+    }
+
+    // Names with the same first letter, for subsequence and camelCase match.
+    if let Some(first) = query.chars().next() {
+        let first = first.to_ascii_lowercase();
+        let next = char::from_u32(first as u32 + 1).unwrap_or(char::MAX);
+
+        let sql = format!(
+            "SELECT {} FROM tags
+            WHERE name COLLATE NOCASE >= ?1 AND name COLLATE NOCASE < ?2
+            LIMIT ?3",
+            TAG_COLUMNS
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let params = rusqlite::params![first.to_string(), next.to_string(), limit];
+        for tag in stmt.query_map(params, tag_from_row)? {
+            let tag = tag?;
+            if seen.insert(tag.id) {
+                ret.push(tag);
+            }
+        }
+    }
+
+    // Names containing the query as a subsequence, for segment match that skips leading
+    // segments, e.g. `td` for `get_tag_data`. No index helps, so it is only tried when the
+    // sources above leave room, on a bounded number of tags, shorter names first.
+    if ret.len() < limit {
+        let pattern = query.chars().fold(String::from("%"), |mut acc, c| {
+            if matches!(c, '%' | '_' | '\\') {
+                acc.push('\\');
+            }
+            acc.push(c);
+            acc.push('%');
+            acc
+        });
+        let sql = format!(
+            "SELECT {} FROM (SELECT * FROM tags LIMIT ?3)
+            WHERE name LIKE ?1 ESCAPE '\\'
+            ORDER BY length(name)
+            LIMIT ?2",
+            TAG_COLUMNS
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let params = rusqlite::params![pattern, limit - ret.len(), MAX_SUBSEQUENCE_SCAN];
+        for tag in stmt.query_map(params, tag_from_row)? {
+            let tag = tag?;
+            if seen.insert(tag.id) {
+                ret.push(tag);
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Columns of `files` table, in the order `file_from_row` expects.
+const FILE_COLUMNS: &str = "path, size, mtime, hash, ptime";
+
+/// Convert a row selected with `FILE_COLUMNS` into `FileInfo`.
+fn file_from_row(row: &rusqlite::Row) -> rusqlite::Result<FileInfo> {
+    let path: String = row.get(0)?;
+
+    Ok(FileInfo {
+        path: path.into(),
+        size: row.get(1)?,
+        mtime: row.get(2)?,
+        hash: row.get(3)?,
+        ptime: row.get(4)?,
+    })
+}
+
+/// Columns of `tags` table, in the order `tag_from_row` expects.
+const TAG_COLUMNS: &str = "id, type, beg_row, beg_col, end_row, end_col,
+    name_beg_row, name_beg_col, name_end_row, name_end_col, path, name, scope, detail";
+
+/// Convert a row selected with `TAG_COLUMNS` into `TagInfo`.
+fn tag_from_row(row: &rusqlite::Row) -> rusqlite::Result<TagInfo> {
+    let kind: i64 = row.get(1)?;
+    let path: String = row.get(10)?;
+
+    Ok(TagInfo {
+        id: row.get(0)?,
+        kind: TagKind::try_from(kind)
+            .map_err(|v| rusqlite::Error::IntegralValueOutOfRange(1, v))?,
+        range: TagRange {
+            beg_row: row.get(2)?,
+            beg_col: row.get(3)?,
+            end_row: row.get(4)?,
+            end_col: row.get(5)?,
+        },
+        name_range: TagRange {
+            beg_row: row.get(6)?,
+            beg_col: row.get(7)?,
+            end_row: row.get(8)?,
+            end_col: row.get(9)?,
+        },
+        path: path.into(),
+        name: row.get(11)?,
+        scope: row.get(12)?,
+        detail: row.get(13)?,
+    })
+}
+
+/// Columns of `xrefs` table, in the order `xref_from_row` expects.
+const XREF_COLUMNS: &str = "id, type, beg_row, beg_col, end_row, end_col, path, name, hold";
+
+/// Convert a row selected with `XREF_COLUMNS` into `XrefInfo`.
+fn xref_from_row(row: &rusqlite::Row) -> rusqlite::Result<XrefInfo> {
+    let kind: i64 = row.get(1)?;
+    let path: String = row.get(6)?;
+
+    Ok(XrefInfo {
+        id: row.get(0)?,
+        kind: XrefKind::try_from(kind)
+            .map_err(|v| rusqlite::Error::IntegralValueOutOfRange(1, v))?,
+        range: TagRange {
+            beg_row: row.get(2)?,
+            beg_col: row.get(3)?,
+            end_row: row.get(4)?,
+            end_col: row.get(5)?,
+        },
+        path: path.into(),
+        name: row.get(7)?,
+        hold: row.get(8)?,
+    })
+}
+
+/// Replace all tags and xrefs of a file.
+///
+/// # Arguments
+///
+/// + `tx` - The transaction.
+/// + `path` - The path of the file.
+/// + `tags` - The tags. `scope` is the index of enclosing tag in `tags`.
+/// + `xrefs` - The xrefs. `hold` is the index of holding tag in `tags`.
+fn replace_index(
+    tx: &rusqlite::Transaction,
+    path: &str,
+    tags: &[TagInfo],
+    xrefs: &[XrefInfo],
+) -> rusqlite::Result<()> {
+    tx.execute("DELETE FROM xrefs WHERE path = ?1;", [path])?;
+    tx.execute("DELETE FROM tags WHERE path = ?1;", [path])?;
+    insert_index(tx, path, tags, xrefs)
+}
+
+/// Insert tags and xrefs of a file.
+///
+/// # Arguments
+///
+/// + `tx` - The transaction.
+/// + `path` - The path of the file.
+/// + `tags` - The tags. `scope` is the index of enclosing tag in `tags`.
+/// + `xrefs` - The xrefs. `hold` is the index of holding tag in `tags`.
+fn insert_index(
+    tx: &rusqlite::Transaction,
+    path: &str,
+    tags: &[TagInfo],
+    xrefs: &[XrefInfo],
+) -> rusqlite::Result<()> {
+    // Row ID of each tag in `tags`, used to resolve `scope`.
+    let mut ids: Vec<i64> = Vec::with_capacity(tags.len());
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO tags (
+                type, beg_row, beg_col, end_row, end_col,
+                name_beg_row, name_beg_col, name_end_row, name_end_col,
+                path, name, scope, detail
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13);",
+        )?;
+
+        for tag in tags {
+            let scope = tag.scope.map(|idx| ids[idx as usize]);
+            stmt.execute(rusqlite::params![
+                tag.kind as i64,
+                tag.range.beg_row,
+                tag.range.beg_col,
+                tag.range.end_row,
+                tag.range.end_col,
+                tag.name_range.beg_row,
+                tag.name_range.beg_col,
+                tag.name_range.end_row,
+                tag.name_range.end_col,
+                &path,
+                &tag.name,
+                scope,
+                &tag.detail,
+            ])?;
+            ids.push(tx.last_insert_rowid());
+        }
+    }
+
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO xrefs (
+                type, beg_row, beg_col, end_row, end_col, path, name, hold
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);",
+        )?;
+
+        for xref in xrefs {
+            let hold = xref.hold.map(|idx| ids[idx as usize]);
+            stmt.execute(rusqlite::params![
+                xref.kind as i64,
+                xref.range.beg_row,
+                xref.range.beg_col,
+                xref.range.end_row,
+                xref.range.end_col,
+                &path,
+                &xref.name,
+                hold,
+            ])?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Sqlite database implementation
 #[derive(Debug, Clone)]
 pub struct SqliteClient {
@@ -22,155 +527,564 @@ impl SqliteClient {
     /// # Arguments
     ///
     /// + `conn` - The database connection.
-    pub fn new(conn: rusqlite::Connection) -> rusqlite::Result<SqliteClient> {
+    pub fn new(mut conn: rusqlite::Connection) -> rusqlite::Result<SqliteClient> {
         tracing::debug!("sqlite version: {}", rusqlite::version());
 
+        // Records belong to their files, do not depend on the default of the build.
+        conn.pragma_update(None, "foreign_keys", true)?;
+        schema::migrate(&mut conn)?;
+
         let client = SqliteClient {
             conn: std::sync::Arc::new(std::sync::Mutex::new(conn)),
         };
 
-        client.initialize_tables()?;
+        Ok(client)
+    }
 
-        return Ok(client);
+    /// Close the database file, after the running transaction is finished.
+    ///
+    /// Later calls use an empty database in memory and fail.
+    pub fn close(&self) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let old = std::mem::replace(&mut *conn, rusqlite::Connection::open_in_memory()?);
+        old.close().map_err(|(_, e)| e)
     }
 
-    pub fn startup_scan(&self, files: &Vec<FileInfo>) -> rusqlite::Result<()> {
-        let conn = self.conn.lock().unwrap();
+    /// Open the database, a corrupt database file is removed and built again.
+    ///
+    /// Other errors, e.g. the database is locked by another server or not readable, are
+    /// returned and the file is kept. So is a file that is not a SQLite database.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the database file, `None` for a database in memory.
+    pub fn open(path: Option<&std::path::Path>) -> rusqlite::Result<SqliteClient> {
+        let path = match path {
+            Some(v) => v,
+            None => return SqliteClient::new(rusqlite::Connection::open_in_memory()?),
+        };
+
+        let err = match rusqlite::Connection::open(path).and_then(SqliteClient::new) {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        };
+        match err.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase) => {}
+            _ => return Err(err),
+        }
+
+        // The path may be mistaken for another file, only a database is removed.
+        if !is_sqlite_file(path) {
+            return Err(err);
+        }
+        tracing::warn!(
+            "database {} is corrupt: {}, rebuild it",
+            path.display(),
+            err
+        );
+
+        // Journal files belong to the old database.
+        for suffix in ["", "-journal", "-wal", "-shm"] {
+            let mut file = path.as_os_str().to_owned();
+            file.push(suffix);
+            match std::fs::remove_file(&file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    tracing::error!("remove {:?} failed: {}", file, e);
+                }
+                _ => {}
+            }
+        }
+
+        SqliteClient::new(rusqlite::Connection::open(path)?)
+    }
+
+    /// Sync the file list with files found on startup.
+    ///
+    /// Records of files that no longer exist are removed, and `size` and `mtime` of the
+    /// others are updated. A file whose hash is given and equals the hash of the parsed
+    /// content is marked as parsed. Files whose `ptime` differs from `mtime` are left for
+    /// analysis.
+    ///
+    /// # Arguments
+    ///
+    /// + `scope` - The scanned directory, `None` if the whole workspace is scanned.
+    /// + `files` - All files found in scope.
+    pub fn startup_scan(
+        &self,
+        scope: Option<&std::path::Path>,
+        files: &[FileInfo],
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
 
         // The `startup_scan_files` table is used to store the files scanned during startup.
-        conn.execute(
-            "CREATE TABLE startup_scan_files (
+        tx.execute(
+            "CREATE TEMP TABLE startup_scan_files (
                 path TEXT PRIMARY KEY NOT NULL,
-                mtime INTEGER
+                size INTEGER,
+                mtime INTEGER,
+                hash INTEGER
             );",
             (),
         )?;
 
         // Insert files into temporary table.
-        for file in files {
-            conn.execute(
-                "INSERT INTO startup_scan_files (path, mtime) VALUES (?1, ?2);",
-                (&file.path.to_str(), file.mtime),
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO startup_scan_files (path, size, mtime, hash)
+                VALUES (?1, ?2, ?3, ?4);",
             )?;
+            for file in files {
+                stmt.execute((&file.path.to_str(), file.size, file.mtime, file.hash))?;
+            }
         }
 
-        self.remove_non_exist_records(&conn)?;
-        self.update_mtime(&conn)?;
+        self.remove_non_exist_records(&tx, scope)?;
+        self.update_mtime(&tx)?;
 
-        // Drop the temopry tables.
-        //conn.execute("DROP TABLE startup_scan_files;", ())?;
+        // Drop the temporary tables.
+        tx.execute("DROP TABLE startup_scan_files;", ())?;
 
-        Ok(())
+        tx.commit()
     }
 
+    /// Get files changed since they were last parsed.
+    ///
+    /// Files that crashed the indexer too many times are skipped until they change.
+    ///
+    /// # Returns
+    ///
+    /// + List of files.
     pub fn pending_analysis(&self) -> rusqlite::Result<Vec<FileInfo>> {
         let mut ret = Vec::new();
         let conn = self.conn.lock().unwrap();
 
-        let mut stmt = conn.prepare("SELECT * FROM files WHERE ptime < mtime")?;
-        let iter = stmt.query_map([], |row| {
-            let path: String = row.get(0)?;
-            Ok(FileInfo {
-                path: path.into(),
-                mtime: row.get(1)?,
-                ptime: row.get(2)?,
-            })
-        })?;
+        let sql = format!(
+            "SELECT {} FROM files WHERE ptime != mtime AND crashes < ?1",
+            FILE_COLUMNS
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let iter = stmt.query_map([MAX_CRASHES], file_from_row)?;
 
         for file in iter {
-            ret.push(file.unwrap());
+            ret.push(file?);
         }
 
-        return Ok(ret);
+        Ok(ret)
     }
 
-    fn update_mtime(
+    /// Get all files in database.
+    ///
+    /// # Returns
+    ///
+    /// + List of files.
+    pub fn find_files(&self) -> rusqlite::Result<Vec<FileInfo>> {
+        let mut ret = Vec::new();
+        let conn = self.conn.lock().unwrap();
+
+        let sql = format!("SELECT {} FROM files", FILE_COLUMNS);
+        let mut stmt = conn.prepare(&sql)?;
+        let iter = stmt.query_map([], file_from_row)?;
+
+        for file in iter {
+            ret.push(file?);
+        }
+
+        Ok(ret)
+    }
+
+    /// Find tags by name.
+    ///
+    /// # Arguments
+    ///
+    /// + `name` - The name of the tag.
+    ///
+    /// # Returns
+    ///
+    /// + List of tags.
+    pub fn find_tags_by_name(&self, name: &str) -> rusqlite::Result<Vec<TagInfo>> {
+        let conn = self.conn.lock().unwrap();
+
+        let sql = format!(
+            "SELECT {} FROM tags WHERE name = ?1 ORDER BY id",
+            TAG_COLUMNS
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let iter = stmt.query_map([name], tag_from_row)?;
+
+        let mut ret = Vec::new();
+        for tag in iter {
+            ret.push(tag?);
+        }
+
+        Ok(ret)
+    }
+
+    /// Find tags that may fuzzy match the query.
+    ///
+    /// Candidates are names containing the query, names starting with the same letter as
+    /// the query, and names containing the query as a subsequence. All are case
+    /// insensitive. The last ones fill up to `limit` from a bounded scan of the table.
+    ///
+    /// # Arguments
+    ///
+    /// + `query` - The query string.
+    /// + `limit` - Max number of candidates from each source.
+    /// + `cancel` - Cancellation of the request, stops the search early.
+    ///
+    /// # Returns
+    ///
+    /// + List of candidate tags, without duplicates.
+    pub fn search_tags(
         &self,
-        conn: &std::sync::MutexGuard<rusqlite::Connection>,
+        query: &str,
+        limit: usize,
+        cancel: &crate::request::CancelToken,
+    ) -> rusqlite::Result<Vec<TagInfo>> {
+        let conn = self.conn.lock().unwrap();
+        interruptible(&conn, cancel, || search_tags(&conn, query, limit))
+    }
+
+    /// Find all tags in a file.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file.
+    ///
+    /// # Returns
+    ///
+    /// + List of tags in source order, enclosing tags come before the tags they contain.
+    pub fn find_tags_by_path(&self, path: &std::path::Path) -> rusqlite::Result<Vec<TagInfo>> {
+        let conn = self.conn.lock().unwrap();
+
+        let sql = format!(
+            "SELECT {} FROM tags WHERE path = ?1 ORDER BY beg_row, beg_col, id",
+            TAG_COLUMNS
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let iter = stmt.query_map([path.to_string_lossy()], tag_from_row)?;
+
+        let mut ret = Vec::new();
+        for tag in iter {
+            ret.push(tag?);
+        }
+
+        Ok(ret)
+    }
+
+    /// Find xrefs by name.
+    ///
+    /// # Arguments
+    ///
+    /// + `name` - The referenced name.
+    /// + `cancel` - Cancellation of the request, stops the search early.
+    ///
+    /// # Returns
+    ///
+    /// + List of xrefs, ordered by path and position.
+    pub fn find_xrefs_by_name(
+        &self,
+        name: &str,
+        cancel: &crate::request::CancelToken,
+    ) -> rusqlite::Result<Vec<XrefInfo>> {
+        let conn = self.conn.lock().unwrap();
+
+        interruptible(&conn, cancel, || {
+            let sql = format!(
+                "SELECT {} FROM xrefs WHERE name = ?1 ORDER BY path, beg_row, beg_col",
+                XREF_COLUMNS
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let iter = stmt.query_map([name], xref_from_row)?;
+
+            let mut ret = Vec::new();
+            for xref in iter {
+                ret.push(xref?);
+            }
+
+            Ok(ret)
+        })
+    }
+
+    /// Replace all tags and xrefs of a file with content that may differ from disk.
+    ///
+    /// The file is marked as not parsed, so it is parsed from disk on next startup.
+    ///
+    /// # Arguments
+    ///
+    /// + `index` - The new index of the file.
+    pub fn update_index(&self, index: &FileIndex) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        // An opened file may be outside of workspace folders.
+        let path = index.path.to_string_lossy();
+        tx.execute(
+            "INSERT INTO files (path, size, mtime, hash, ptime) VALUES (?1, 0, 0, NULL, 0)
+            ON CONFLICT(path) DO UPDATE SET ptime = 0, hash = NULL;",
+            [&path],
+        )?;
+        replace_index(&tx, &path, &index.tags, &index.xrefs)?;
+
+        tx.commit()
+    }
+
+    /// Replace all tags and xrefs of many files parsed from disk, in one transaction.
+    ///
+    /// `ptime` of each file is stamped with the `mtime` found on startup and the hash of
+    /// parsed content is saved, within the same transaction, so a file is only skipped on
+    /// next startup if its index is committed.
+    ///
+    /// # Arguments
+    ///
+    /// + `files` - The new index of each file.
+    pub fn update_index_batch(&self, files: &[FileIndex]) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        for index in files {
+            let path = index.path.to_string_lossy();
+            replace_index(&tx, &path, &index.tags, &index.xrefs)?;
+            tx.execute(
+                "UPDATE files SET ptime = mtime, hash = ?2, crashes = 0 WHERE path = ?1;",
+                rusqlite::params![&path, index.hash],
+            )?;
+        }
+
+        tx.commit()
+    }
+
+    /// Replace tags and xrefs in some rows of a file, and move the rows after them.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file.
+    /// + `beg_row` - The first replaced row, before the change.
+    /// + `end_row` - The last replaced row, before the change.
+    /// + `row_delta` - The number of rows added by the change, negative if removed.
+    /// + `tags` - The new tags in replaced rows. `scope` is the index of enclosing tag in `tags`.
+    /// + `xrefs` - The new xrefs in replaced rows. `hold` is the index of holding tag in `tags`.
+    pub fn patch_index(
+        &self,
+        path: &std::path::Path,
+        beg_row: i64,
+        end_row: i64,
+        row_delta: i64,
+        tags: &[TagInfo],
+        xrefs: &[XrefInfo],
     ) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let path = path.to_string_lossy();
+
+        tx.execute(
+            "DELETE FROM xrefs WHERE path = ?1 AND beg_row BETWEEN ?2 AND ?3;",
+            rusqlite::params![&path, beg_row, end_row],
+        )?;
+        tx.execute(
+            "DELETE FROM tags WHERE path = ?1 AND beg_row BETWEEN ?2 AND ?3;",
+            rusqlite::params![&path, beg_row, end_row],
+        )?;
+
+        if row_delta != 0 {
+            tx.execute(
+                "UPDATE xrefs SET beg_row = beg_row + ?3, end_row = end_row + ?3
+                WHERE path = ?1 AND beg_row > ?2;",
+                rusqlite::params![&path, end_row, row_delta],
+            )?;
+            tx.execute(
+                "UPDATE tags SET
+                    beg_row = beg_row + ?3, end_row = end_row + ?3,
+                    name_beg_row = name_beg_row + ?3, name_end_row = name_end_row + ?3
+                WHERE path = ?1 AND beg_row > ?2;",
+                rusqlite::params![&path, end_row, row_delta],
+            )?;
+        }
+
+        insert_index(&tx, &path, tags, xrefs)?;
+        tx.execute(
+            "UPDATE files SET ptime = 0, hash = NULL WHERE path = ?1;",
+            [&path],
+        )?;
+
+        tx.commit()
+    }
+
+    /// Add files or update their `size` and `mtime`, they are parsed later.
+    ///
+    /// # Arguments
+    ///
+    /// + `files` - The files.
+    pub fn update_files(&self, files: &[FileInfo]) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO files (path, size, mtime, hash, ptime)
+                VALUES (?1, ?2, ?3, NULL, 0)
+                ON CONFLICT(path) DO UPDATE SET
+                    size = EXCLUDED.size,
+                    mtime = EXCLUDED.mtime,
+                    crashes = CASE
+                        WHEN files.mtime = EXCLUDED.mtime THEN files.crashes
+                        ELSE 0
+                    END;",
+            )?;
+            for file in files {
+                stmt.execute((&file.path.to_string_lossy(), file.size, file.mtime))?;
+            }
+        }
+
+        tx.commit()
+    }
+
+    /// Count a crash of the indexer while parsing a file.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file.
+    ///
+    /// # Returns
+    ///
+    /// + `true` if the file crashed too many times and is no longer parsed.
+    pub fn record_crash(&self, path: &std::path::Path) -> rusqlite::Result<bool> {
+        use rusqlite::OptionalExtension;
+
+        let conn = self.conn.lock().unwrap();
+
+        let crashes: Option<i64> = conn
+            .query_row(
+                "UPDATE files SET crashes = crashes + 1 WHERE path = ?1 RETURNING crashes;",
+                [path.to_string_lossy()],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(crashes.is_some_and(|v| v >= MAX_CRASHES))
+    }
+
+    /// Mark all files to be parsed again, e.g. after the way they are read changed.
+    pub fn invalidate_all(&self) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("UPDATE files SET ptime = 0;", ())?;
+        Ok(())
+    }
+
+    /// Remove all records of a file, or of all files in a directory.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file or directory.
+    pub fn remove_path(&self, path: &std::path::Path) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let path = path.to_string_lossy();
+
+        // Xrefs refer to tags, so they go first.
+        for table in ["xrefs", "tags", "files"] {
+            let sql = format!("DELETE FROM {} WHERE {};", table, PATH_OR_CHILDREN);
+            tx.execute(&sql, [&path])?;
+        }
+
+        tx.commit()
+    }
+
+    /// Move all records of a file, or of all files in a directory, to a new path.
+    ///
+    /// # Arguments
+    ///
+    /// + `from` - The old path of the file or directory.
+    /// + `to` - The new path.
+    pub fn rename_path(
+        &self,
+        from: &std::path::Path,
+        to: &std::path::Path,
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let from = from.to_string_lossy();
+        let to = to.to_string_lossy();
+
+        // Paths of records and their files change together, `files` is updated last, so
+        // foreign keys are only checked on commit.
+        tx.pragma_update(None, "defer_foreign_keys", true)?;
+
+        // Records at the new path are replaced.
+        for table in ["xrefs", "tags", "files"] {
+            let sql = format!("DELETE FROM {} WHERE {};", table, PATH_OR_CHILDREN);
+            tx.execute(&sql, [&to])?;
+        }
+        for table in ["xrefs", "tags", "files"] {
+            let sql = format!(
+                "UPDATE {} SET path = ?2 || substr(path, length(?1) + 1) WHERE {};",
+                table, PATH_OR_CHILDREN
+            );
+            tx.execute(&sql, [&from, &to])?;
+        }
+
+        tx.commit()
+    }
+
+    fn update_mtime(&self, conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+        // A touched file with the same content does not need parsing. The hash of a new
+        // file is never computed, the hash of parsed content is saved after parsing.
         conn.execute(
-            "INSERT INTO files (path, mtime, ptime)
-            SELECT path, mtime, 0
+            "INSERT INTO files (path, size, mtime, hash, ptime)
+            SELECT path, size, mtime, hash, 0
             FROM startup_scan_files
             WHERE true
             ON CONFLICT(path) DO UPDATE SET
-                mtime = EXCLUDED.mtime;",
+                size = EXCLUDED.size,
+                mtime = EXCLUDED.mtime,
+                ptime = CASE
+                    WHEN files.hash = EXCLUDED.hash THEN EXCLUDED.mtime
+                    ELSE files.ptime
+                END,
+                crashes = CASE
+                    WHEN files.mtime = EXCLUDED.mtime THEN files.crashes
+                    ELSE 0
+                END;",
             (),
-        )
-        .unwrap();
+        )?;
 
         Ok(())
     }
 
     fn remove_non_exist_records(
         &self,
-        conn: &std::sync::MutexGuard<rusqlite::Connection>,
+        conn: &rusqlite::Connection,
+        scope: Option<&std::path::Path>,
     ) -> rusqlite::Result<()> {
         // Create table `files_to_delete`.
         conn.execute(
-            "CREATE TABLE files_to_delete (
+            "CREATE TEMP TABLE files_to_delete (
                 path TEXT PRIMARY KEY NOT NULL
             );",
             (),
         )?;
 
-        // Find all files that do not exist in the filesystem.
-        conn.execute(
+        // Find all files in scope that do not exist in the filesystem.
+        let sql = format!(
             "INSERT INTO files_to_delete (path)
             SELECT f.path
             FROM files f
-            WHERE NOT EXISTS (
+            WHERE (?1 IS NULL OR {})
+            AND NOT EXISTS (
                 SELECT 1
                 FROM startup_scan_files tf
                 WHERE tf.path = f.path
             );",
-            (),
-        )?;
+            PATH_OR_CHILDREN
+        );
+        conn.execute(&sql, [scope.and_then(|v| v.to_str())])?;
 
-        //conn.execute("DROP TABLE files_to_delete;", ())?;
-
-        Ok(())
-    }
-
-    /// Setup required tables.
-    fn initialize_tables(&self) -> rusqlite::Result<()> {
-        let conn = self.conn.lock().unwrap();
-
-        // The `files`` table is used to store the file information.
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS files (
-                path TEXT PRIMARY KEY NOT NULL,
-                mtime INTEGER,
-                ptime INTEGER
-            )",
-            (),
-        )?;
-
-        // The `tags` table is used to store the tag information.
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS tags (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                type INTEGER,
-                beg_row INTEGER,
-                beg_col INTEGER,
-                end_row INTEGER,
-                end_col INTEGER,
-                path TEXT,
-                name TEXT,
-                FOREIGN KEY(path) REFERENCES files(path)
-            )",
-            (),
-        )?;
-
-        // The `xrefs` table is used to store the xref information.
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS xrefs (
-                name INTEGER,
-                hold INTEGER,
-                FOREIGN KEY(name) REFERENCES tags(id),
-                FOREIGN KEY(hold) REFERENCES tags(id)
-            )",
-            (),
+        // Xrefs refer to tags, so they go first.
+        conn.execute_batch(
+            "DELETE FROM xrefs WHERE path IN (SELECT path FROM files_to_delete);
+            DELETE FROM tags WHERE path IN (SELECT path FROM files_to_delete);
+            DELETE FROM files WHERE path IN (SELECT path FROM files_to_delete);
+            DROP TABLE files_to_delete;",
         )?;
 
         Ok(())
@@ -0,0 +1,161 @@
+/// Ordered schema migrations. Migration `i` upgrades `user_version` from `i` to `i + 1`.
+///
+/// A released migration must never change, append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: Files, tags with trigram name search, and xrefs.
+    "CREATE TABLE files (
+        path TEXT PRIMARY KEY NOT NULL,
+        size INTEGER,
+        mtime INTEGER,
+        hash INTEGER,
+        ptime INTEGER
+    );
+
+    CREATE TABLE tags (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        type INTEGER,
+        beg_row INTEGER,
+        beg_col INTEGER,
+        end_row INTEGER,
+        end_col INTEGER,
+        name_beg_row INTEGER,
+        name_beg_col INTEGER,
+        name_end_row INTEGER,
+        name_end_col INTEGER,
+        path TEXT,
+        name TEXT,
+        scope INTEGER,
+        detail TEXT,
+        FOREIGN KEY(path) REFERENCES files(path),
+        FOREIGN KEY(scope) REFERENCES tags(id)
+    );
+    CREATE INDEX tags_path ON tags(path);
+    CREATE INDEX tags_name ON tags(name);
+    CREATE INDEX tags_name_nocase ON tags(name COLLATE NOCASE);
+
+    CREATE VIRTUAL TABLE tags_fts USING fts5(
+        name, content='tags', content_rowid='id', tokenize='trigram'
+    );
+    CREATE TRIGGER tags_fts_insert AFTER INSERT ON tags BEGIN
+        INSERT INTO tags_fts(rowid, name) VALUES (new.id, new.name);
+    END;
+    CREATE TRIGGER tags_fts_delete AFTER DELETE ON tags BEGIN
+        INSERT INTO tags_fts(tags_fts, rowid, name) VALUES ('delete', old.id, old.name);
+    END;
+
+    CREATE TABLE xrefs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        type INTEGER,
+        beg_row INTEGER,
+        beg_col INTEGER,
+        end_row INTEGER,
+        end_col INTEGER,
+        path TEXT,
+        name TEXT,
+        hold INTEGER,
+        FOREIGN KEY(path) REFERENCES files(path),
+        FOREIGN KEY(hold) REFERENCES tags(id)
+    );
+    CREATE INDEX xrefs_path ON xrefs(path);
+    CREATE INDEX xrefs_name ON xrefs(name);",
+    // 2: Number of times parsing a file crashed the indexer.
+    "ALTER TABLE files ADD COLUMN crashes INTEGER NOT NULL DEFAULT 0;",
+];
+
+/// Upgrade the schema to the latest version.
+///
+/// A database built before versioning, or by a newer version of this program, is
+/// dropped and built again.
+///
+/// # Arguments
+///
+/// + `conn` - The database connection.
+///
+/// # Returns
+///
+/// + Error if the database is corrupt or the migration fails.
+pub fn migrate(conn: &mut rusqlite::Connection) -> rusqlite::Result<()> {
+    check_integrity(conn)?;
+
+    let mut version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let has_tables: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name NOT LIKE 'sqlite_%')",
+        [],
+        |row| row.get(0),
+    )?;
+
+    if version > MIGRATIONS.len() || (version == 0 && has_tables) {
+        tracing::warn!(
+            "incompatible database schema version {}, rebuild it",
+            version
+        );
+        drop_all(conn)?;
+        version = 0;
+    }
+
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        tracing::info!("migrate database schema to version {}", i + 1);
+
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Check the database is not corrupt.
+///
+/// # Arguments
+///
+/// + `conn` - The database connection.
+fn check_integrity(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    let result: String = conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+    if result == "ok" {
+        return Ok(());
+    }
+
+    Err(rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CORRUPT),
+        Some(result),
+    ))
+}
+
+/// Drop all tables, views and triggers.
+///
+/// # Arguments
+///
+/// + `conn` - The database connection.
+fn drop_all(conn: &mut rusqlite::Connection) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+
+    // Dropping a table deletes its rows first, tables referring to it are gone on commit.
+    tx.pragma_update(None, "defer_foreign_keys", true)?;
+
+    // Virtual tables go before plain tables, as they drop their own shadow tables.
+    let objects: Vec<(String, String)> = {
+        let mut stmt = tx.prepare(
+            "SELECT type, name FROM sqlite_master
+            WHERE type IN ('table', 'view', 'trigger') AND name NOT LIKE 'sqlite_%'
+            ORDER BY CASE
+                WHEN type != 'table' THEN 0
+                WHEN sql LIKE 'CREATE VIRTUAL%' THEN 1
+                ELSE 2
+            END",
+        )?;
+        let iter = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        iter.collect::<rusqlite::Result<_>>()?
+    };
+
+    for (kind, name) in objects {
+        tx.execute_batch(&format!(
+            "DROP {} IF EXISTS \"{}\";",
+            kind.to_uppercase(),
+            name.replace('"', "\"\"")
+        ))?;
+    }
+    tx.pragma_update(None, "user_version", 0)?;
+
+    tx.commit()
+}
@@ -0,0 +1,141 @@
+/// A document opened by the client.
+#[derive(Debug, Clone)]
+pub struct Document {
+    /// The language ID reported by the client.
+    pub language_id: String,
+
+    /// The version of the document.
+    pub version: i32,
+
+    /// The content of the document.
+    pub text: String,
+
+    /// The syntax tree of the content, if the language is supported.
+    pub tree: Option<tree_sitter::Tree>,
+}
+
+/// In-memory overlay of documents opened by the client.
+///
+/// An open document shadows the file on disk until it is closed.
+#[derive(Debug, Default, Clone)]
+pub struct DocumentStore {
+    docs: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<std::path::PathBuf, Document>>>,
+
+    /// Decoding of files on disk.
+    encodings: crate::encoding::Encodings,
+}
+
+impl DocumentStore {
+    /// Create an empty document store.
+    ///
+    /// # Arguments
+    ///
+    /// + `encodings` - Decoding of files on disk.
+    pub fn new(encodings: crate::encoding::Encodings) -> DocumentStore {
+        DocumentStore {
+            docs: Default::default(),
+            encodings,
+        }
+    }
+
+    /// Save an opened document.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the document.
+    /// + `doc` - The document.
+    pub fn open(&self, path: &std::path::Path, doc: Document) {
+        let mut docs = self.docs.lock().unwrap();
+        docs.insert(path.to_path_buf(), doc);
+    }
+
+    /// Replace the content of an opened document.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the document.
+    /// + `version` - The new version.
+    /// + `text` - The new content.
+    /// + `tree` - The syntax tree of the new content.
+    ///
+    /// # Returns
+    ///
+    /// + `false` if the document is not opened or the version is older than the stored one.
+    pub fn update(
+        &self,
+        path: &std::path::Path,
+        version: i32,
+        text: String,
+        tree: Option<tree_sitter::Tree>,
+    ) -> bool {
+        let mut docs = self.docs.lock().unwrap();
+        match docs.get_mut(path) {
+            Some(doc) if doc.version <= version => {
+                doc.version = version;
+                doc.text = text;
+                doc.tree = tree;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Replace the syntax tree of an opened document.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the document.
+    /// + `tree` - The syntax tree.
+    pub fn set_tree(&self, path: &std::path::Path, tree: Option<tree_sitter::Tree>) {
+        let mut docs = self.docs.lock().unwrap();
+        if let Some(doc) = docs.get_mut(path) {
+            doc.tree = tree;
+        }
+    }
+
+    /// Remove a closed document.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the document.
+    pub fn close(&self, path: &std::path::Path) {
+        let mut docs = self.docs.lock().unwrap();
+        docs.remove(path);
+    }
+
+    /// Get an opened document.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the document.
+    ///
+    /// # Returns
+    ///
+    /// + The document, or `None` if not opened.
+    pub fn get(&self, path: &std::path::Path) -> Option<Document> {
+        let docs = self.docs.lock().unwrap();
+        docs.get(path).cloned()
+    }
+
+    /// Get the paths of all opened documents.
+    pub fn paths(&self) -> Vec<std::path::PathBuf> {
+        let docs = self.docs.lock().unwrap();
+        docs.keys().cloned().collect()
+    }
+
+    /// Read the content of a file, the opened document wins over the file on disk.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file.
+    ///
+    /// # Returns
+    ///
+    /// + The content.
+    pub fn read(&self, path: &std::path::Path) -> std::io::Result<String> {
+        match self.get(path) {
+            Some(doc) => Ok(doc.text),
+            None => Ok(self.encodings.read(path)?.1),
+        }
+    }
+}
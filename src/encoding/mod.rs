@@ -0,0 +1,142 @@
+#[derive(Debug)]
+struct EncodingsInner {
+    /// Encoding of files that are neither marked by BOM nor valid UTF-8.
+    default: &'static encoding_rs::Encoding,
+
+    /// Patterns of files with a known encoding.
+    patterns: globset::GlobSet,
+
+    /// Encoding of each pattern.
+    overrides: Vec<&'static encoding_rs::Encoding>,
+}
+
+/// Decoding of source files on disk, shared by all threads.
+///
+/// The encoding of a file is picked in order: byte order mark, the first matched
+/// override, UTF-8 if the content is valid, then the default. Bytes that are not
+/// valid in the picked encoding are replaced, so every file can be indexed.
+#[derive(Debug, Default, Clone)]
+pub struct Encodings {
+    inner: std::sync::Arc<std::sync::RwLock<EncodingsInner>>,
+}
+
+impl Encodings {
+    pub fn new() -> Encodings {
+        Encodings::default()
+    }
+
+    /// Change the encodings, nothing changes if any label or pattern is invalid.
+    ///
+    /// # Arguments
+    ///
+    /// + `default` - Label of the default encoding, e.g. `gbk`. UTF-8 if `None`.
+    /// + `overrides` - Glob patterns and labels of their encodings. Relative patterns
+    ///   match anywhere in the path.
+    pub fn configure(
+        &self,
+        default: Option<&str>,
+        overrides: &[(String, String)],
+    ) -> crate::Result<()> {
+        let default = match default {
+            Some(v) => for_label(v)?,
+            None => encoding_rs::UTF_8,
+        };
+        let patterns: Vec<_> = overrides.iter().map(|(k, _)| k).collect();
+        let patterns = crate::settings::compile_globs(&patterns)?;
+        let overrides = overrides
+            .iter()
+            .map(|(_, v)| for_label(v))
+            .collect::<crate::Result<_>>()?;
+
+        *self.inner.write().unwrap() = EncodingsInner {
+            default,
+            patterns,
+            overrides,
+        };
+        Ok(())
+    }
+
+    /// Read a file on disk as text.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file.
+    ///
+    /// # Returns
+    ///
+    /// + The raw content, and the decoded text.
+    pub fn read(&self, path: &std::path::Path) -> std::io::Result<(Vec<u8>, String)> {
+        let content = std::fs::read(path)?;
+        let text = self.decode(path, &content);
+        Ok((content, text))
+    }
+
+    /// Decode the content of a file.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file.
+    /// + `content` - The raw content.
+    ///
+    /// # Returns
+    ///
+    /// + The text, without byte order mark.
+    pub fn decode(&self, path: &std::path::Path, content: &[u8]) -> String {
+        let (encoding, content) = match encoding_rs::Encoding::for_bom(content) {
+            Some((encoding, len)) => (encoding, &content[len..]),
+            None => (self.pick(path, content), content),
+        };
+
+        let (text, malformed) = encoding.decode_without_bom_handling(content);
+        if malformed {
+            tracing::warn!(
+                "{} is not valid {}, invalid bytes are replaced",
+                path.display(),
+                encoding.name()
+            );
+        }
+        text.into_owned()
+    }
+
+    /// Pick the encoding of a file without byte order mark.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file.
+    /// + `content` - The raw content.
+    fn pick(&self, path: &std::path::Path, content: &[u8]) -> &'static encoding_rs::Encoding {
+        let inner = self.inner.read().unwrap();
+        if let Some(i) = inner.patterns.matches(path).first() {
+            return inner.overrides[*i];
+        }
+
+        match std::str::from_utf8(content) {
+            Ok(_) => encoding_rs::UTF_8,
+            Err(_) => inner.default,
+        }
+    }
+}
+
+impl Default for EncodingsInner {
+    fn default() -> Self {
+        EncodingsInner {
+            default: encoding_rs::UTF_8,
+            patterns: globset::GlobSet::empty(),
+            overrides: Vec::new(),
+        }
+    }
+}
+
+/// Find encoding by its label.
+///
+/// # Arguments
+///
+/// + `label` - The label, e.g. `utf-8`, `latin1`, `gbk` or `shift_jis`.
+///
+/// # Returns
+///
+/// + The encoding.
+fn for_label(label: &str) -> crate::Result<&'static encoding_rs::Encoding> {
+    encoding_rs::Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| crate::Error::Encoding(format!("unknown encoding {}", label)))
+}
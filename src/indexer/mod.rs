@@ -0,0 +1,569 @@
+use lsp_types::notification::Notification;
+use lsp_types::request::Request;
+use lsp_types::*;
+use rayon::prelude::*;
+
+/// Prefix of work done progress tokens for workspace indexing, each scan has its own.
+const PROGRESS_TOKEN: &str = "syntaxforest/indexing";
+
+/// How long to wait for the client to create a progress token.
+const PROGRESS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Max number of files saved in one transaction.
+const WRITE_BATCH: usize = 256;
+
+/// Background indexing of workspace folders.
+///
+/// Files are parsed on a pool of worker threads, so the server answers requests with
+/// what is indexed so far. Jobs run one after another in queued order.
+#[derive(Debug, Default, Clone)]
+pub struct Indexer {
+    /// Set to ask the worker to stop.
+    stop: std::sync::Arc<std::sync::atomic::AtomicBool>,
+
+    /// Queue of jobs, dropped to let the worker exit.
+    jobs: std::sync::Arc<std::sync::Mutex<Option<crossbeam_channel::Sender<Job>>>>,
+
+    /// The worker thread.
+    handle: std::sync::Arc<std::sync::Mutex<Option<std::thread::JoinHandle<()>>>>,
+
+    /// Requests sent to the client, waiting for responses.
+    responses: Responses,
+}
+
+/// Channels waiting for responses of requests sent to the client, by request id.
+type Responses = std::sync::Arc<
+    std::sync::Mutex<
+        std::collections::HashMap<
+            lsp_server::RequestId,
+            crossbeam_channel::Sender<lsp_server::Response>,
+        >,
+    >,
+>;
+
+/// Work queued for the worker thread.
+#[derive(Debug)]
+enum Job {
+    /// Index all workspace folders, records of other files are removed.
+    Startup(Vec<std::path::PathBuf>),
+
+    /// Index a folder added to workspace.
+    Add(std::path::PathBuf),
+
+    /// Remove records of a folder removed from workspace.
+    Remove(std::path::PathBuf),
+
+    /// Index changed files or directories again.
+    Refresh(Vec<std::path::PathBuf>),
+}
+
+impl Indexer {
+    pub fn new() -> Indexer {
+        Indexer::default()
+    }
+
+    /// Start indexing all workspace folders.
+    ///
+    /// # Arguments
+    ///
+    /// + `rt` - The runtime.
+    /// + `sender` - Channel to the client, used for progress report.
+    pub fn start(
+        &self,
+        rt: &crate::LspRuntime,
+        sender: crossbeam_channel::Sender<lsp_server::Message>,
+    ) {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(rt.settings.get().threads.unwrap_or(0))
+            .thread_name(|i| format!("indexer-{}", i))
+            .build();
+        let pool = match pool {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("create indexing threads failed: {}", e);
+                return;
+            }
+        };
+
+        let worker = Worker {
+            db: rt.db.clone(),
+            parser: rt.parser.clone(),
+            documents: rt.documents.clone(),
+            encodings: rt.encodings.clone(),
+            settings: rt.settings.clone(),
+            stop: self.stop.clone(),
+            responses: self.responses.clone(),
+            scans: Default::default(),
+            pool,
+            progress: match work_done_progress(&rt.capabilities) {
+                true => Some(sender),
+                false => None,
+            },
+        };
+
+        let (jobs, receiver) = crossbeam_channel::unbounded();
+        let roots = rt
+            .workspace_folders
+            .iter()
+            .filter_map(|v| v.uri.to_file_path().ok())
+            .collect();
+        jobs.send(Job::Startup(roots)).ok();
+
+        let handle = std::thread::spawn(move || {
+            for job in receiver {
+                if worker.stop.load(std::sync::atomic::Ordering::Relaxed) {
+                    break;
+                }
+                if let Err(e) = worker.run(&job) {
+                    tracing::error!("indexing {:?} failed: {}", job, e);
+                }
+            }
+        });
+        *self.jobs.lock().unwrap() = Some(jobs);
+        *self.handle.lock().unwrap() = Some(handle);
+    }
+
+    /// Index a folder added to workspace.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the folder.
+    pub fn add_folder(&self, path: std::path::PathBuf) {
+        self.push(Job::Add(path));
+    }
+
+    /// Remove records of a folder removed from workspace.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the folder.
+    pub fn remove_folder(&self, path: std::path::PathBuf) {
+        self.push(Job::Remove(path));
+    }
+
+    /// Index files again after they changed on disk.
+    ///
+    /// Records of paths that no longer exist are removed, directories are walked.
+    ///
+    /// # Arguments
+    ///
+    /// + `paths` - Changed files or directories.
+    pub fn refresh(&self, paths: Vec<std::path::PathBuf>) {
+        if !paths.is_empty() {
+            self.push(Job::Refresh(paths));
+        }
+    }
+
+    /// Pass a response of the client to the request waiting for it.
+    ///
+    /// # Arguments
+    ///
+    /// + `rsp` - The response.
+    pub fn handle_response(&self, rsp: lsp_server::Response) {
+        if let Some(sender) = self.responses.lock().unwrap().remove(&rsp.id) {
+            sender.send(rsp).ok();
+        }
+    }
+
+    /// Stop indexing and wait for the worker to exit.
+    pub fn stop(&self) {
+        self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        self.jobs.lock().unwrap().take();
+
+        // Responses are no longer read, do not wait for them.
+        self.responses.lock().unwrap().clear();
+
+        let handle = self.handle.lock().unwrap().take();
+        if let Some(handle) = handle {
+            handle.join().ok();
+        }
+    }
+
+    /// Queue a job for the worker.
+    ///
+    /// # Arguments
+    ///
+    /// + `job` - The job.
+    fn push(&self, job: Job) {
+        if let Some(jobs) = self.jobs.lock().unwrap().as_ref() {
+            jobs.send(job).ok();
+        }
+    }
+}
+
+/// Parse a file on disk, errors and crashes skip the file.
+///
+/// A crash is counted in database, files that crash too often are quarantined.
+///
+/// # Arguments
+///
+/// + `db` - The database.
+/// + `parser` - The parser.
+/// + `encodings` - Decoding of the file.
+/// + `path` - The path of the file.
+///
+/// # Returns
+///
+/// + The index of the file, or `None` if the file is skipped.
+fn parse_file(
+    db: &crate::db::SqliteClient,
+    parser: &crate::syntax::SyntaxParser,
+    encodings: &crate::encoding::Encodings,
+    path: &std::path::Path,
+) -> Option<crate::db::FileIndex> {
+    let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        parser.index_file(path, encodings)
+    }));
+    match ret {
+        Ok(Ok(v)) => Some(v),
+        Ok(Err(e)) => {
+            tracing::warn!("parse {} failed: {}", path.display(), e);
+            None
+        }
+        Err(e) => {
+            let msg = crate::utils::panic_message(e.as_ref());
+            tracing::error!("parse {} crashed: {}", path.display(), msg);
+            match db.record_crash(path) {
+                Ok(true) => tracing::warn!("{} is quarantined", path.display()),
+                Ok(false) => {}
+                Err(e) => tracing::error!("record crash of {} failed: {}", path.display(), e),
+            }
+            None
+        }
+    }
+}
+
+/// State moved into the worker thread.
+struct Worker {
+    db: crate::db::SqliteClient,
+    parser: crate::syntax::SyntaxParser,
+    documents: crate::document::DocumentStore,
+    encodings: crate::encoding::Encodings,
+    settings: crate::settings::Settings,
+    stop: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    responses: Responses,
+
+    /// Number of scans started, to make progress tokens unique.
+    scans: std::sync::atomic::AtomicU64,
+
+    /// Threads that parse files.
+    pool: rayon::ThreadPool,
+
+    /// Channel to the client, `None` if the client does not support progress.
+    progress: Option<crossbeam_channel::Sender<lsp_server::Message>>,
+}
+
+impl Worker {
+    /// Run a job.
+    ///
+    /// # Arguments
+    ///
+    /// + `job` - The job.
+    fn run(&self, job: &Job) -> crate::Result<()> {
+        match job {
+            Job::Startup(roots) => self.scan(roots, None),
+            Job::Add(path) => self.scan(std::slice::from_ref(path), Some(path)),
+            Job::Remove(path) => Ok(self.db.remove_path(path)?),
+            Job::Refresh(paths) => self.refresh(paths),
+        }
+    }
+
+    /// Index files again after they changed on disk.
+    ///
+    /// # Arguments
+    ///
+    /// + `paths` - Changed files or directories.
+    fn refresh(&self, paths: &[std::path::PathBuf]) -> crate::Result<()> {
+        let mut file_list = Vec::new();
+        for path in paths {
+            match std::fs::metadata(path) {
+                Ok(v) if v.is_dir() => {
+                    file_list.append(&mut crate::utils::path::walk_with_gitignore(path.clone())?)
+                }
+                Ok(v) => file_list.push(crate::utils::path::file_info(path, &v)?),
+                Err(_) => self.db.remove_path(path)?,
+            }
+        }
+
+        // Records of files excluded by settings are removed.
+        let (file_list, excluded): (Vec<_>, Vec<_>) = self
+            .parser
+            .filter_files(&file_list)
+            .into_iter()
+            .partition(|v| self.settings.is_indexed(v));
+        for file in &excluded {
+            self.db.remove_path(&file.path)?;
+        }
+
+        // Opened documents are indexed from their content by text sync.
+        let file_list: Vec<_> = file_list
+            .into_iter()
+            .filter(|v| self.documents.get(&v.path).is_none())
+            .collect();
+        self.db.update_files(&file_list)?;
+
+        tracing::debug!("refreshing {} files", file_list.len());
+        self.index(&file_list, None)
+    }
+
+    /// Scan folders and parse files changed since last run.
+    ///
+    /// # Arguments
+    ///
+    /// + `roots` - The folders to scan.
+    /// + `scope` - Records outside of it are kept, `None` to keep only files found.
+    fn scan(
+        &self,
+        roots: &[std::path::PathBuf],
+        scope: Option<&std::path::Path>,
+    ) -> crate::Result<()> {
+        let mut file_list = Vec::new();
+        for path in roots {
+            file_list.append(&mut crate::utils::path::walk_with_gitignore(path.clone())?);
+        }
+        let mut file_list: Vec<_> = self
+            .parser
+            .filter_files(&file_list)
+            .into_iter()
+            .filter(|v| self.settings.is_indexed(v))
+            .collect();
+
+        // Hash only files that look changed, to tell if the content really changed.
+        let stored: std::collections::HashMap<_, _> = self
+            .db
+            .find_files()?
+            .into_iter()
+            .map(|v| (v.path.clone(), v))
+            .collect();
+        self.pool.install(|| {
+            file_list.par_iter_mut().for_each(|file| {
+                if let Some(old) = stored.get(&file.path) {
+                    if old.hash.is_some() && (old.size, old.mtime) != (file.size, file.mtime) {
+                        file.hash = crate::utils::path::file_hash(&file.path).ok();
+                    }
+                }
+            });
+        });
+        self.db.startup_scan(scope, &file_list)?;
+
+        let files = self.db.pending_analysis()?;
+        let total = files.len();
+        tracing::info!("indexing {} files", total);
+
+        let token = self.create_progress()?;
+        self.send_progress(
+            token.as_ref(),
+            WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: "Indexing".to_string(),
+                cancellable: Some(false),
+                message: Some(format!("0/{} files", total)),
+                percentage: Some(0),
+            }),
+        )?;
+
+        self.index(&files, token.as_ref())?;
+
+        self.send_progress(
+            token.as_ref(),
+            WorkDoneProgress::End(WorkDoneProgressEnd {
+                message: Some(format!("{} files indexed", total)),
+            }),
+        )?;
+        tracing::info!("indexing finished");
+
+        Ok(())
+    }
+
+    /// Parse files in parallel, a single writer saves the results in batches.
+    ///
+    /// # Arguments
+    ///
+    /// + `files` - The files.
+    /// + `progress` - The progress token, `None` to not report progress.
+    fn index(
+        &self,
+        files: &[crate::db::FileInfo],
+        progress: Option<&NumberOrString>,
+    ) -> crate::Result<()> {
+        let (sender, receiver) = crossbeam_channel::bounded(WRITE_BATCH * 4);
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| self.write(receiver, files.len(), progress));
+
+            self.pool.install(|| {
+                files.par_iter().for_each_with(sender, |sender, file| {
+                    sender.send(self.parse(&file.path)).ok();
+                });
+            });
+
+            match writer.join() {
+                Ok(v) => v,
+                Err(_) => Err(crate::Error::Io(std::io::Error::other(
+                    "index writer panicked",
+                ))),
+            }
+        })
+    }
+
+    /// Parse a file on disk.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file.
+    ///
+    /// # Returns
+    ///
+    /// + The index of the file, or `None` if the file is skipped.
+    fn parse(&self, path: &std::path::Path) -> Option<crate::db::FileIndex> {
+        if self.stop.load(std::sync::atomic::Ordering::Relaxed) {
+            return None;
+        }
+
+        // Opened documents are indexed from their content by text sync.
+        if self.documents.get(path).is_some() {
+            return None;
+        }
+
+        parse_file(&self.db, &self.parser, &self.encodings, path)
+    }
+
+    /// Save parsed files into database, in batches.
+    ///
+    /// # Arguments
+    ///
+    /// + `receiver` - Results of parsed files, `None` for skipped ones.
+    /// + `total` - The number of files to index.
+    /// + `progress` - The progress token, `None` to not report progress.
+    fn write(
+        &self,
+        receiver: crossbeam_channel::Receiver<Option<crate::db::FileIndex>>,
+        total: usize,
+        progress: Option<&NumberOrString>,
+    ) -> crate::Result<()> {
+        let mut batch = Vec::with_capacity(WRITE_BATCH);
+        let mut done = 0;
+        let mut percentage = 0;
+
+        // Wait for one file, then take whatever is ready so the writer keeps up.
+        while let Ok(first) = receiver.recv() {
+            batch.extend(first);
+            done += 1;
+            while done % WRITE_BATCH != 0 {
+                match receiver.try_recv() {
+                    Ok(v) => {
+                        batch.extend(v);
+                        done += 1;
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            self.db.update_index_batch(&batch)?;
+            batch.clear();
+
+            // Report only when percentage changes, to avoid flooding the client.
+            let current = (done * 100 / total) as u32;
+            if current != percentage {
+                percentage = current;
+                self.send_progress(
+                    progress,
+                    WorkDoneProgress::Report(WorkDoneProgressReport {
+                        cancellable: Some(false),
+                        message: Some(format!("{}/{} files", done, total)),
+                        percentage: Some(percentage),
+                    }),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ask the client to create a progress token for a scan, and wait for the answer.
+    ///
+    /// # Returns
+    ///
+    /// + The token, or `None` if the client does not support progress, refused it or
+    ///   did not answer in time.
+    fn create_progress(&self) -> crate::Result<Option<NumberOrString>> {
+        let sender = match &self.progress {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        let n = self
+            .scans
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let token = NumberOrString::String(format!("{}/{}", PROGRESS_TOKEN, n));
+        let id: lsp_server::RequestId = format!("{}/create/{}", PROGRESS_TOKEN, n).into();
+
+        let (rsp_sender, receiver) = crossbeam_channel::bounded(1);
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(id.clone(), rsp_sender);
+        let req = lsp_server::Request::new(
+            id.clone(),
+            request::WorkDoneProgressCreate::METHOD.to_string(),
+            WorkDoneProgressCreateParams {
+                token: token.clone(),
+            },
+        );
+        sender.send(lsp_server::Message::Request(req))?;
+
+        // Progress must not be reported before the client creates the token.
+        let rsp = receiver.recv_timeout(PROGRESS_TIMEOUT);
+        self.responses.lock().unwrap().remove(&id);
+        match rsp {
+            Ok(lsp_server::Response { error: None, .. }) => Ok(Some(token)),
+            Ok(lsp_server::Response { error: Some(e), .. }) => {
+                tracing::warn!("client refused to create progress: {}", e.message);
+                Ok(None)
+            }
+            Err(_) => {
+                tracing::warn!("client did not create progress, indexing is not reported");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Send `$/progress` notification.
+    ///
+    /// # Arguments
+    ///
+    /// + `token` - The progress token, nothing is sent if `None`.
+    /// + `value` - The progress.
+    fn send_progress(
+        &self,
+        token: Option<&NumberOrString>,
+        value: WorkDoneProgress,
+    ) -> crate::Result<()> {
+        let (sender, token) = match (&self.progress, token) {
+            (Some(sender), Some(token)) => (sender, token),
+            _ => return Ok(()),
+        };
+
+        let nfy = lsp_server::Notification::new(
+            notification::Progress::METHOD.to_string(),
+            ProgressParams {
+                token: token.clone(),
+                value: ProgressParamsValue::WorkDone(value),
+            },
+        );
+        sender.send(lsp_server::Message::Notification(nfy))?;
+
+        Ok(())
+    }
+}
+
+/// Whether the client supports server initiated progress.
+///
+/// # Arguments
+///
+/// + `capabilities` - The capabilities of the client.
+fn work_done_progress(capabilities: &ClientCapabilities) -> bool {
+    capabilities
+        .window
+        .as_ref()
+        .and_then(|v| v.work_done_progress)
+        .unwrap_or(false)
+}
@@ -1,14 +1,127 @@
 mod db;
+mod document;
+mod encoding;
+mod indexer;
 mod method;
+mod monitor;
+mod request;
+mod settings;
 mod syntax;
+mod transport;
 mod utils;
+mod watcher;
 
 use lsp_types::request::Request;
 
 /// The error type used in this crate.
 #[derive(Debug)]
 pub enum Error {
-    Unknown,
+    /// Reading or writing files, or the transport, failed.
+    Io(std::io::Error),
+
+    /// The database failed.
+    Sqlite(rusqlite::Error),
+
+    /// A source file cannot be parsed.
+    Parse(String),
+
+    /// A message from the client is malformed or cannot be sent.
+    Protocol(String),
+
+    /// A path is not valid text, or an encoding is unknown.
+    Encoding(String),
+
+    /// An option is invalid.
+    Config(String),
+
+    /// The request is canceled.
+    Canceled,
+}
+
+impl Error {
+    /// Get the error code used in response to a request.
+    ///
+    /// # Returns
+    ///
+    /// + The LSP error code.
+    pub fn code(&self) -> i32 {
+        let code = match self {
+            Error::Io(_) | Error::Sqlite(_) => lsp_server::ErrorCode::InternalError,
+            Error::Parse(_) | Error::Encoding(_) => lsp_server::ErrorCode::RequestFailed,
+            Error::Protocol(_) | Error::Config(_) => lsp_server::ErrorCode::InvalidParams,
+            Error::Canceled => lsp_server::ErrorCode::RequestCanceled,
+        };
+        code as i32
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Sqlite(e) => write!(f, "database error: {}", e),
+            Error::Parse(e) => write!(f, "parse error: {}", e),
+            Error::Protocol(e) => write!(f, "protocol error: {}", e),
+            Error::Encoding(e) => write!(f, "encoding error: {}", e),
+            Error::Config(e) => write!(f, "config error: {}", e),
+            Error::Canceled => write!(f, "request canceled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Sqlite(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Sqlite(e)
+    }
+}
+
+impl From<notify::Error> for Error {
+    fn from(e: notify::Error) -> Self {
+        match e.kind {
+            notify::ErrorKind::Io(e) => Error::Io(e),
+            _ => Error::Io(std::io::Error::other(e.to_string())),
+        }
+    }
+}
+
+impl From<rayon::ThreadPoolBuildError> for Error {
+    fn from(e: rayon::ThreadPoolBuildError) -> Self {
+        Error::Io(std::io::Error::other(e.to_string()))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Protocol(e.to_string())
+    }
+}
+
+impl From<lsp_server::ProtocolError> for Error {
+    fn from(e: lsp_server::ProtocolError) -> Self {
+        Error::Protocol(e.to_string())
+    }
+}
+
+impl<T> From<crossbeam_channel::SendError<T>> for Error {
+    fn from(_: crossbeam_channel::SendError<T>) -> Self {
+        Error::Protocol(String::from("connection closed"))
+    }
 }
 
 /// The result type used in this crate.
@@ -16,25 +129,51 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(Debug, clap::Parser, Default, Clone)]
 #[command(author, version, about, long_about = None)]
+#[command(group(clap::ArgGroup::new("transport").args(["stdio", "port", "pipe", "listen"])))]
 pub struct LspConfig {
-    #[arg(
-        long,
-        conflicts_with = "port",
-        help = "Uses stdio as the communication channel"
-    )]
+    #[arg(long, help = "Uses stdio as the communication channel")]
     pub stdio: bool,
 
     #[arg(
         long,
-        conflicts_with = "stdio",
+        alias = "socket",
         help = "Uses a socket as the communication channel",
-        long_help = "The LSP server start as TCP client and connect to the specified port"
+        long_help = "The LSP server start as TCP client and connect to the specified port. `--socket=PORT` is the same"
     )]
     pub port: Option<u16>,
 
-    #[arg(long, value_name = "PATH", help = "Specifies a database file to use")]
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Uses a Unix domain socket as the communication channel",
+        long_help = "The LSP server connect to the Unix domain socket at PATH, created by the client"
+    )]
+    pub pipe: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        help = "Accepts a connection on a TCP port or a Unix domain socket",
+        long_help = "The LSP server accepts one connection on ADDR, which is a port on localhost, HOST:PORT, or the path of a Unix domain socket"
+    )]
+    pub listen: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Specifies a database file to use",
+        long_help = "Can also be set by setting `database`"
+    )]
     pub dbfile: Option<String>,
 
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Specifies a user configuration file to use",
+        long_help = "By default `syntaxforest/config.toml` in the user configuration directory is used. Settings are overridden by `.syntaxforest.toml` in workspace folders, the command line, initialization options and `workspace/didChangeConfiguration`, in order"
+    )]
+    pub config: Option<String>,
+
     #[arg(
         long,
         value_name = "DIR",
@@ -49,6 +188,56 @@ pub struct LspConfig {
         long_help = "Possible values are: [OFF | TRACE | DEBUG | INFO | WARN | ERROR] (By default `INFO` is used)"
     )]
     pub loglevel: Option<String>,
+
+    #[arg(
+        long,
+        help = "Watches workspace folders for changes on disk",
+        long_help = "For clients that do not send `workspace/didChangeWatchedFiles`. Can also be enabled by setting `watch`"
+    )]
+    pub watch: bool,
+
+    #[arg(
+        long,
+        value_name = "LABEL",
+        help = "Encoding of source files that are not UTF-8",
+        long_help = "Used for files without byte order mark that are not valid UTF-8, e.g. `latin1`, `gbk` or `shift_jis`. Can also be set by setting `encoding`"
+    )]
+    pub encoding: Option<String>,
+
+    #[arg(
+        long = "encoding-override",
+        value_name = "GLOB=LABEL",
+        value_parser = parse_encoding_override,
+        help = "Encoding of source files matching a pattern",
+        long_help = "May be given more than once, patterns are tried in sorted order. Relative patterns match anywhere in the path. Can also be set by setting `encodingOverrides`, a table of patterns to labels"
+    )]
+    pub encoding_override: Vec<(String, String)>,
+
+    #[arg(
+        long = "clientProcessId",
+        value_name = "PID",
+        help = "Exits when the client process exits",
+        long_help = "The process id of the client. The process id in `initialize` request is used if present"
+    )]
+    pub client_process_id: Option<u32>,
+}
+
+/// Parse `GLOB=LABEL` of `--encoding-override`.
+///
+/// # Arguments
+///
+/// + `value` - The value of the option.
+///
+/// # Returns
+///
+/// + The pattern and the label.
+fn parse_encoding_override(value: &str) -> std::result::Result<(String, String), String> {
+    match value.rsplit_once('=') {
+        Some((glob, label)) if !glob.is_empty() && !label.is_empty() => {
+            Ok((glob.to_string(), label.to_string()))
+        }
+        _ => Err(format!("expect GLOB=LABEL, got {}", value)),
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -56,11 +245,42 @@ pub struct LspRuntime {
     /// The list of workspace folders.
     pub workspace_folders: Vec<lsp_types::WorkspaceFolder>,
 
+    /// The capabilities of the client.
+    pub capabilities: lsp_types::ClientCapabilities,
+
+    /// The unit of columns negotiated with the client.
+    pub position_encoding: crate::utils::position::PositionEncoding,
+
     /// The database.
     pub db: crate::db::SqliteClient,
 
     /// File association to language parser.
     pub parser: crate::syntax::SyntaxParser,
+
+    /// Documents opened by the client.
+    pub documents: crate::document::DocumentStore,
+
+    /// Decoding of source files on disk.
+    pub encodings: crate::encoding::Encodings,
+
+    /// Settings merged from all sources.
+    pub settings: crate::settings::Settings,
+
+    /// Background indexing of workspace folders.
+    pub indexer: crate::indexer::Indexer,
+
+    /// Server side file watcher.
+    pub watcher: crate::watcher::Watcher,
+
+    /// The process id of the client, the server exits when it exits.
+    pub client_process_id: Option<u32>,
+
+    /// Number of times file watchers were registered with the client, `None` if the
+    /// client does not watch files for the server.
+    pub client_watchers: Option<u64>,
+
+    /// Cancellation of the request being handled, never canceled for notifications.
+    pub cancel: crate::request::CancelToken,
 }
 
 /// Start the LSP server.
@@ -71,8 +291,8 @@ pub struct LspRuntime {
 ///
 /// # Returns
 ///
-/// + `Result<(), Box<dyn std::error::Error + Sync + Send>>`
-pub fn start_lsp(config: &LspConfig) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+/// + `Result<()>`
+pub fn start_lsp(config: &LspConfig) -> Result<()> {
     const PROG_NAME: &str = env!("CARGO_PKG_NAME");
     const PROG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -82,50 +302,71 @@ pub fn start_lsp(config: &LspConfig) -> Result<(), Box<dyn std::error::Error + S
     tracing::info!("PID: {}", std::process::id());
 
     // Create the transport.
-    let (connection, io_threads) = if config.stdio {
-        lsp_server::Connection::stdio()
-    } else {
-        let port = config.port.expect("port is required");
-        lsp_server::Connection::connect(format!("127.0.0.1:{}", port)).unwrap()
-    };
+    let (connection, io_threads) = transport::open(config)?;
 
     // Initialize the server.
     tracing::info!("initialize...");
-    let runtime = match method::initialize::initialize(&connection, config) {
-        Ok(v) => v,
-        Err(e) => {
-            return Err(e.into());
-        }
-    };
+    let runtime = method::initialize::initialize(&connection, config)?;
 
     // Start the server.
     tracing::info!("starting lsp");
-    message_loop(runtime, connection)?;
-    io_threads.join()?;
+    let client_exit = match runtime.client_process_id {
+        Some(pid) => monitor::watch(pid),
+        None => crossbeam_channel::never(),
+    };
+    match message_loop(runtime, connection, client_exit)? {
+        // The client is gone, nobody closes the transport.
+        false => tracing::warn!("exit without shutdown"),
+        true => io_threads.join()?,
+    }
 
     Ok(())
 }
 
+/// Parse the value of `--loglevel`.
+///
+/// # Arguments
+///
+/// + `value` - The value, case insensitive.
+///
+/// # Returns
+///
+/// + The log level.
+fn parse_loglevel(value: &str) -> Result<tracing::metadata::LevelFilter> {
+    match value.to_lowercase().as_str() {
+        "off" => Ok(tracing::metadata::LevelFilter::OFF),
+        "trace" => Ok(tracing::metadata::LevelFilter::TRACE),
+        "debug" => Ok(tracing::metadata::LevelFilter::DEBUG),
+        "info" => Ok(tracing::metadata::LevelFilter::INFO),
+        "warn" => Ok(tracing::metadata::LevelFilter::WARN),
+        "error" => Ok(tracing::metadata::LevelFilter::ERROR),
+        unmatched => Err(Error::Config(format!(
+            "unknown option value `{}` for --loglevel",
+            unmatched
+        ))),
+    }
+}
+
+/// Setup logging, an invalid log level falls back to `INFO` and is reported to the
+/// client once it is connected.
 fn setup_logging_system(config: &LspConfig, prog_name: &str) {
-    // Get log level.
-    let loglevel = match &config.loglevel {
-        Some(v) => v.clone(),
-        None => String::from("INFO"),
-    };
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
 
-    // Parse log level.
-    let loglevel = match loglevel.to_lowercase().as_str() {
-        "off" => tracing::metadata::LevelFilter::OFF,
-        "trace" => tracing::metadata::LevelFilter::TRACE,
-        "debug" => tracing::metadata::LevelFilter::DEBUG,
-        "info" => tracing::metadata::LevelFilter::INFO,
-        "warn" => tracing::metadata::LevelFilter::WARN,
-        "error" => tracing::metadata::LevelFilter::ERROR,
-        unmatched => panic!(
-            "Parser command line argument failed: unknown option value `{}`",
-            unmatched
-        ),
-    };
+    let loglevel = config
+        .loglevel
+        .as_deref()
+        .and_then(|v| parse_loglevel(v).ok())
+        .unwrap_or(tracing::metadata::LevelFilter::INFO);
+
+    // The file watcher traces every event it reads, with a log file in a watched folder
+    // each line would be read as another event, forever.
+    let filter = tracing_subscriber::filter::Targets::new()
+        .with_default(loglevel)
+        .with_target(
+            "notify",
+            loglevel.min(tracing::metadata::LevelFilter::DEBUG),
+        );
 
     match &config.logdir {
         Some(path) => {
@@ -135,13 +376,19 @@ fn setup_logging_system(config: &LspConfig, prog_name: &str) {
                 .with_max_level(loglevel)
                 .with_writer(file_appender)
                 .with_ansi(false)
-                .init();
+                .finish()
+                .with(filter)
+                .try_init()
+                .ok();
         }
         None => {
             tracing_subscriber::fmt()
                 .with_max_level(loglevel)
                 .with_writer(std::io::stderr)
-                .init();
+                .finish()
+                .with(filter)
+                .try_init()
+                .ok();
         }
     }
     std::panic::set_hook(Box::new(tracing_panic::panic_hook));
@@ -156,44 +403,307 @@ fn setup_logging_system(config: &LspConfig, prog_name: &str) {
 ///
 /// # Returns
 ///
-/// + `Result<(), Box<dyn std::error::Error + Sync + Send>>`
+/// + `true` if the client asked to shutdown, `false` if the client process exited.
 ///
 fn message_loop(
     mut backend: LspRuntime,
     connection: lsp_server::Connection,
-) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
-    for msg in &connection.receiver {
+    client_exit: crossbeam_channel::Receiver<()>,
+) -> Result<bool> {
+    let requests = request::Requests::new()?;
+
+    loop {
+        let msg = crossbeam_channel::select! {
+            recv(connection.receiver) -> msg => match msg {
+                Ok(v) => v,
+                Err(_) => break,
+            },
+            recv(client_exit) -> _ => {
+                method::shutdown::shutdown(&mut backend)?;
+                return Ok(false);
+            }
+        };
+
         match msg {
             lsp_server::Message::Request(req) => {
-                if connection.handle_shutdown(&req)? {
-                    method::shutdown::shutdown(&mut backend)?;
-                    return Ok(());
+                if handle_shutdown(&mut backend, &connection, &req)? {
+                    return Ok(true);
                 }
 
-                handle_request(&mut backend, &connection, req)?;
+                handle_request(&backend, &requests, &connection, req);
             }
 
-            lsp_server::Message::Response(_rsp) => {}
+            lsp_server::Message::Response(rsp) => backend.indexer.handle_response(rsp),
 
-            lsp_server::Message::Notification(_nfy) => {}
+            lsp_server::Message::Notification(nfy) => {
+                // A failed notification has no one to report to.
+                let method = nfy.method.clone();
+                let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    handle_notification(&mut backend, &requests, &connection, nfy)
+                }));
+                match ret {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => tracing::error!("{} failed: {}", method, e),
+                    Err(e) => {
+                        tracing::error!("{} crashed: {}", method, utils::panic_message(e.as_ref()))
+                    }
+                }
+            }
         }
     }
 
+    // The transport is closed without shutdown.
+    method::shutdown::shutdown(&mut backend)?;
+    Ok(true)
+}
+
+/// Answer a shutdown request and wait for the exit notification.
+///
+/// The server is shut down before the response, so the client may exit once answered.
+/// Unlike `lsp_server::Connection::handle_shutdown`, responses to requests the server
+/// sent before, e.g. to create progress, may still arrive and are skipped. Other
+/// requests are refused and notifications are dropped until exit.
+///
+/// # Arguments
+///
+/// + `backend` - The runtime.
+/// + `connection` - The connection to client.
+/// + `req` - The request.
+///
+/// # Returns
+///
+/// + `true` if the request is shutdown and the client exited.
+fn handle_shutdown(
+    backend: &mut LspRuntime,
+    connection: &lsp_server::Connection,
+    req: &lsp_server::Request,
+) -> Result<bool> {
+    use lsp_types::notification::Notification;
+    use lsp_types::request::Request;
+
+    if req.method != lsp_types::request::Shutdown::METHOD {
+        return Ok(false);
+    }
+    let rsp = match method::shutdown::shutdown(backend) {
+        Ok(()) => lsp_server::Response::new_ok(req.id.clone(), ()),
+        Err(e) => lsp_server::Response::new_err(req.id.clone(), e.code(), e.to_string()),
+    };
+    connection.sender.send(rsp.into())?;
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+    loop {
+        match connection.receiver.recv_deadline(deadline) {
+            Ok(lsp_server::Message::Notification(v))
+                if v.method == lsp_types::notification::Exit::METHOD =>
+            {
+                return Ok(true)
+            }
+            Ok(lsp_server::Message::Request(v)) => {
+                let rsp = lsp_server::Response::new_err(
+                    v.id,
+                    lsp_server::ErrorCode::InvalidRequest as i32,
+                    String::from("server is shut down"),
+                );
+                connection.sender.send(rsp.into())?;
+            }
+            Ok(lsp_server::Message::Notification(v)) => {
+                tracing::debug!("drop notification {} after shutdown", v.method);
+            }
+            Ok(lsp_server::Message::Response(_)) => {}
+            Err(e) => {
+                return Err(Error::Protocol(format!(
+                    "waiting for exit notification failed: {}",
+                    e
+                )))
+            }
+        }
+    }
+}
+
+fn handle_notification(
+    rt: &mut LspRuntime,
+    requests: &request::Requests,
+    conn: &lsp_server::Connection,
+    nfy: lsp_server::Notification,
+) -> Result<()> {
+    use lsp_types::notification::Notification;
+
+    // Running requests would answer with results of old content.
+    if is_edit(&nfy.method) {
+        requests.modified();
+    }
+
+    match nfy.method.as_str() {
+        lsp_types::notification::Cancel::METHOD => {
+            let p: lsp_types::CancelParams = serde_json::from_value(nfy.params)?;
+            let id = match p.id {
+                lsp_types::NumberOrString::Number(v) => v.into(),
+                lsp_types::NumberOrString::String(v) => v.into(),
+            };
+            requests.cancel(&id);
+        }
+
+        lsp_types::notification::DidOpenTextDocument::METHOD => {
+            let p = serde_json::from_value(nfy.params)?;
+            method::text_document_sync::did_open(rt, p)?;
+        }
+
+        lsp_types::notification::DidChangeTextDocument::METHOD => {
+            let p = serde_json::from_value(nfy.params)?;
+            method::text_document_sync::did_change(rt, p)?;
+        }
+
+        lsp_types::notification::DidSaveTextDocument::METHOD => {
+            let p = serde_json::from_value(nfy.params)?;
+            method::text_document_sync::did_save(rt, p)?;
+        }
+
+        lsp_types::notification::DidCloseTextDocument::METHOD => {
+            let p = serde_json::from_value(nfy.params)?;
+            method::text_document_sync::did_close(rt, p)?;
+        }
+
+        lsp_types::notification::DidCreateFiles::METHOD => {
+            let p = serde_json::from_value(nfy.params)?;
+            method::file_operations::did_create_files(rt, p)?;
+        }
+
+        lsp_types::notification::DidRenameFiles::METHOD => {
+            let p = serde_json::from_value(nfy.params)?;
+            method::file_operations::did_rename_files(rt, p)?;
+        }
+
+        lsp_types::notification::DidDeleteFiles::METHOD => {
+            let p = serde_json::from_value(nfy.params)?;
+            method::file_operations::did_delete_files(rt, p)?;
+        }
+
+        lsp_types::notification::DidChangeWatchedFiles::METHOD => {
+            let p = serde_json::from_value(nfy.params)?;
+            method::file_operations::did_change_watched_files(rt, p)?;
+        }
+
+        lsp_types::notification::DidChangeWorkspaceFolders::METHOD => {
+            let p = serde_json::from_value(nfy.params)?;
+            method::workspace_folders::did_change_workspace_folders(rt, p)?;
+        }
+
+        lsp_types::notification::DidChangeConfiguration::METHOD => {
+            let p = serde_json::from_value(nfy.params)?;
+            method::configuration::did_change_configuration(rt, &conn.sender, p)?;
+        }
+
+        _ => {}
+    }
+
     Ok(())
 }
 
+/// Handle a request on a worker thread.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime, cloned for the request.
+/// + `requests` - Running requests.
+/// + `conn` - The connection to the client.
+/// + `req` - The request.
 fn handle_request(
-    rt: &mut LspRuntime,
+    rt: &LspRuntime,
+    requests: &request::Requests,
     conn: &lsp_server::Connection,
     req: lsp_server::Request,
-) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
-    let id = req.id.clone();
-    let mut rsp = match req.method.as_str() {
+) {
+    let mut rt = rt.clone();
+    let sender = conn.sender.clone();
+
+    requests.spawn(req.id.clone(), conn.sender.clone(), move |token| {
+        rt.cancel = token.clone();
+
+        let method = req.method.clone();
+        let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            dispatch_request(&mut rt, &sender, req)
+        }));
+        match ret {
+            Ok(Ok(v)) => v,
+            Ok(Err(e)) => {
+                tracing::error!("{} failed: {}", method, e);
+                lsp_server::Response::new_err(0.into(), e.code(), e.to_string())
+            }
+            Err(e) => {
+                let msg = utils::panic_message(e.as_ref());
+                tracing::error!("{} crashed: {}", method, msg);
+                lsp_server::Response::new_err(
+                    0.into(),
+                    lsp_server::ErrorCode::InternalError as i32,
+                    format!("internal error: {}", msg),
+                )
+            }
+        }
+    });
+}
+
+/// Check whether a notification changes content that requests read.
+///
+/// Opening a document shadows the file on disk, closing it reads the disk again, and
+/// configuration or workspace folder changes rescan or remove records.
+///
+/// # Arguments
+///
+/// + `method` - The method of the notification.
+fn is_edit(method: &str) -> bool {
+    use lsp_types::notification::*;
+
+    [
+        DidOpenTextDocument::METHOD,
+        DidChangeTextDocument::METHOD,
+        DidCloseTextDocument::METHOD,
+        DidChangeConfiguration::METHOD,
+        DidChangeWorkspaceFolders::METHOD,
+        DidCreateFiles::METHOD,
+        DidRenameFiles::METHOD,
+        DidDeleteFiles::METHOD,
+        DidChangeWatchedFiles::METHOD,
+    ]
+    .contains(&method)
+}
+
+/// Call the handler of a request.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime.
+/// + `sender` - Channel to the client.
+/// + `req` - The request.
+///
+/// # Returns
+///
+/// + The response, its id is set by caller.
+fn dispatch_request(
+    rt: &mut LspRuntime,
+    sender: &crossbeam_channel::Sender<lsp_server::Message>,
+    req: lsp_server::Request,
+) -> Result<lsp_server::Response> {
+    let rsp = match req.method.as_str() {
         lsp_types::request::GotoDefinition::METHOD => {
             let p = serde_json::from_value(req.params)?;
             method::goto_definition::goto_definition(rt, p)?
         }
 
+        lsp_types::request::DocumentSymbolRequest::METHOD => {
+            let p = serde_json::from_value(req.params)?;
+            method::document_symbol::document_symbol(rt, p)?
+        }
+
+        lsp_types::request::References::METHOD => {
+            let p = serde_json::from_value(req.params)?;
+            method::references::references(rt, sender, p)?
+        }
+
+        lsp_types::request::WorkspaceSymbolRequest::METHOD => {
+            let p = serde_json::from_value(req.params)?;
+            method::workspace_symbol::workspace_symbol(rt, p)?
+        }
+
         // Method not found.
         _ => lsp_server::Response {
             id: 0.into(),
@@ -206,8 +716,5 @@ fn handle_request(
         },
     };
 
-    rsp.id = id;
-    conn.sender.send(lsp_server::Message::Response(rsp))?;
-
-    Ok(())
+    Ok(rsp)
 }
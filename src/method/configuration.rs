@@ -0,0 +1,192 @@
+use lsp_types::notification::Notification;
+use lsp_types::*;
+
+use crate::settings::{Layer, Source};
+
+pub fn did_change_configuration(
+    rt: &mut crate::LspRuntime,
+    sender: &crossbeam_channel::Sender<lsp_server::Message>,
+    params: DidChangeConfigurationParams,
+) -> crate::Result<()> {
+    // Clients that send all their settings put ours in a section.
+    let value = match params.settings.get("syntaxforest") {
+        Some(v) => v.clone(),
+        None => params.settings,
+    };
+
+    let old = rt.settings.get();
+    let base = roots(&rt.workspace_folders).into_iter().next();
+    let ret = Layer::from_json(value, base.as_deref())
+        .and_then(|layer| rt.settings.set(Source::Client, layer));
+    if let Err(e) = ret {
+        return report(sender, vec![e]);
+    }
+    let new = rt.settings.get();
+
+    let errors = apply(rt);
+    if (&new.database, new.threads, new.watch) != (&old.database, old.threads, old.watch) {
+        tracing::warn!("database, threads and watch take effect after restart");
+    }
+
+    // Files are read in another way, parse all of them again.
+    if (&new.encoding, &new.encoding_overrides) != (&old.encoding, &old.encoding_overrides) {
+        rt.db.invalidate_all()?;
+    }
+    if (
+        &new.associations,
+        &new.exclude,
+        new.max_file_size,
+        &new.encoding,
+        &new.encoding_overrides,
+    ) != (
+        &old.associations,
+        &old.exclude,
+        old.max_file_size,
+        &old.encoding,
+        &old.encoding_overrides,
+    ) {
+        for root in roots(&rt.workspace_folders) {
+            rt.indexer.add_folder(root);
+        }
+    }
+
+    // The client watches again with the current patterns when files to index change.
+    if (&new.associations, &new.exclude) != (&old.associations, &old.exclude)
+        && rt.client_watchers.is_some()
+    {
+        super::file_operations::register_watchers(rt, sender)?;
+    }
+
+    report(sender, errors)
+}
+
+/// Load settings from all sources at startup, invalid ones are skipped.
+///
+/// From low to high priority: the user file, `.syntaxforest.toml` in workspace folders,
+/// the command line and initialization options.
+///
+/// # Arguments
+///
+/// + `settings` - The settings.
+/// + `config` - The LSP configuration.
+/// + `params` - The initialize params.
+/// + `folders` - The workspace folders.
+///
+/// # Returns
+///
+/// + Errors of invalid sources.
+pub fn load(
+    settings: &crate::settings::Settings,
+    config: &crate::LspConfig,
+    params: &InitializeParams,
+    folders: &[WorkspaceFolder],
+) -> Vec<crate::Error> {
+    let mut errors = Vec::new();
+
+    let user_file = config
+        .config
+        .clone()
+        .map(std::path::PathBuf::from)
+        .or_else(crate::settings::user_file);
+    if let Some(path) = user_file {
+        match Layer::from_file(&path) {
+            Ok(Some(layer)) => errors.extend(settings.set(Source::UserFile, layer).err()),
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+
+    // Later folders win.
+    let roots = roots(folders);
+    let mut workspace = Layer::default();
+    for root in &roots {
+        match Layer::from_file(&root.join(crate::settings::WORKSPACE_FILE)) {
+            Ok(Some(layer)) => workspace.merge(layer),
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    // A cloned repository must not choose which file the server writes.
+    if workspace.database.take().is_some() {
+        errors.push(crate::Error::Config(format!(
+            "database in {} is ignored, set it in the user file or by the client",
+            crate::settings::WORKSPACE_FILE
+        )));
+    }
+    errors.extend(settings.set(Source::WorkspaceFile, workspace).err());
+
+    if let Some(Err(e)) = config.loglevel.as_deref().map(crate::parse_loglevel) {
+        errors.push(e);
+    }
+    let layer = Layer::from_command_line(config);
+    errors.extend(settings.set(Source::CommandLine, layer).err());
+
+    let options = params.initialization_options.clone().unwrap_or_default();
+    let ret = Layer::from_json(options, roots.first().map(|v| v.as_path()))
+        .and_then(|layer| settings.set(Source::InitializationOptions, layer));
+    errors.extend(ret.err());
+
+    errors
+}
+
+/// Apply settings to parts of the runtime that keep their own state.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime.
+///
+/// # Returns
+///
+/// + Errors of invalid values, the parts they configure keep the old ones.
+pub fn apply(rt: &crate::LspRuntime) -> Vec<crate::Error> {
+    let settings = rt.settings.get();
+    let mut errors = Vec::new();
+
+    let associations = settings.associations.unwrap_or_default();
+    errors.extend(rt.parser.set_associations(&associations).err());
+
+    let overrides: Vec<_> = settings.encoding_overrides.into_iter().flatten().collect();
+    errors.extend(
+        rt.encodings
+            .configure(settings.encoding.as_deref(), &overrides)
+            .err(),
+    );
+
+    errors
+}
+
+/// Show errors to the user.
+///
+/// # Arguments
+///
+/// + `sender` - Channel to the client.
+/// + `errors` - The errors.
+pub fn report(
+    sender: &crossbeam_channel::Sender<lsp_server::Message>,
+    errors: Vec<crate::Error>,
+) -> crate::Result<()> {
+    for e in errors {
+        tracing::error!("{}", e);
+        let nfy = lsp_server::Notification::new(
+            notification::ShowMessage::METHOD.to_string(),
+            ShowMessageParams {
+                typ: MessageType::ERROR,
+                message: e.to_string(),
+            },
+        );
+        sender.send(lsp_server::Message::Notification(nfy))?;
+    }
+    Ok(())
+}
+
+/// Get paths of workspace folders.
+///
+/// # Arguments
+///
+/// + `folders` - The workspace folders.
+fn roots(folders: &[WorkspaceFolder]) -> Vec<std::path::PathBuf> {
+    folders
+        .iter()
+        .filter_map(|v| v.uri.to_file_path().ok())
+        .collect()
+}
@@ -0,0 +1,147 @@
+use lsp_types::*;
+
+use crate::db::TagInfo;
+
+pub fn document_symbol(
+    rt: &mut crate::LspRuntime,
+    params: DocumentSymbolParams,
+) -> crate::Result<lsp_server::Response> {
+    let uri = params.text_document.uri;
+    let path = match uri.to_file_path() {
+        Ok(v) => v,
+        Err(_) => {
+            return Ok(lsp_server::Response::new_ok(
+                0.into(),
+                serde_json::Value::Null,
+            ))
+        }
+    };
+    let tags = rt.db.find_tags_by_path(&path)?;
+
+    let hierarchical = rt
+        .capabilities
+        .text_document
+        .as_ref()
+        .and_then(|v| v.document_symbol.as_ref())
+        .and_then(|v| v.hierarchical_document_symbol_support)
+        .unwrap_or(false);
+
+    let mut positions = super::PositionMapper::new(rt);
+    let result = match hierarchical {
+        true => DocumentSymbolResponse::Nested(build_nested(&mut positions, &path, &tags)),
+        false => DocumentSymbolResponse::Flat(build_flat(&mut positions, &path, &uri, &tags)),
+    };
+
+    Ok(lsp_server::Response::new_ok(0.into(), result))
+}
+
+/// Build the outline tree.
+///
+/// # Arguments
+///
+/// + `positions` - Converter of positions.
+/// + `path` - The path of the file.
+/// + `tags` - All tags in the file, enclosing tags come first.
+///
+/// # Returns
+///
+/// + Top level symbols.
+fn build_nested(
+    positions: &mut super::PositionMapper,
+    path: &std::path::Path,
+    tags: &[TagInfo],
+) -> Vec<DocumentSymbol> {
+    // Children of each tag, by index in `tags`.
+    let index: std::collections::HashMap<i64, usize> =
+        tags.iter().enumerate().map(|(i, v)| (v.id, i)).collect();
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); tags.len()];
+    let mut roots = Vec::new();
+    for (i, tag) in tags.iter().enumerate() {
+        match tag.scope.and_then(|v| index.get(&v)) {
+            Some(parent) => children[*parent].push(i),
+            None => roots.push(i),
+        }
+    }
+
+    // Children are built before their parent, which always has a smaller index.
+    let mut built: Vec<Option<DocumentSymbol>> = vec![None; tags.len()];
+    for i in (0..tags.len()).rev() {
+        let nested: Vec<_> = children[i]
+            .iter()
+            .filter_map(|v| built[*v].take())
+            .collect();
+        built[i] = Some(to_document_symbol(positions, path, &tags[i], nested));
+    }
+
+    roots.iter().filter_map(|v| built[*v].take()).collect()
+}
+
+/// Build the flat symbol list.
+///
+/// # Arguments
+///
+/// + `positions` - Converter of positions.
+/// + `path` - The path of the file.
+/// + `uri` - The uri of the file.
+/// + `tags` - All tags in the file.
+///
+/// # Returns
+///
+/// + List of symbols.
+fn build_flat(
+    positions: &mut super::PositionMapper,
+    path: &std::path::Path,
+    uri: &Url,
+    tags: &[TagInfo],
+) -> Vec<SymbolInformation> {
+    let names: std::collections::HashMap<i64, &str> =
+        tags.iter().map(|v| (v.id, v.name.as_str())).collect();
+
+    tags.iter()
+        .map(|tag| {
+            #[allow(deprecated)]
+            SymbolInformation {
+                name: tag.name.clone(),
+                kind: super::to_lsp_symbol_kind(tag.kind),
+                tags: None,
+                deprecated: None,
+                location: Location::new(uri.clone(), positions.lsp_range(path, &tag.range)),
+                container_name: tag.scope.and_then(|v| names.get(&v)).map(|v| v.to_string()),
+            }
+        })
+        .collect()
+}
+
+/// Convert tag into outline item.
+///
+/// # Arguments
+///
+/// + `positions` - Converter of positions.
+/// + `path` - The path of the file.
+/// + `tag` - The tag.
+/// + `children` - Outline items enclosed by the tag.
+///
+/// # Returns
+///
+/// + Outline item.
+fn to_document_symbol(
+    positions: &mut super::PositionMapper,
+    path: &std::path::Path,
+    tag: &TagInfo,
+    children: Vec<DocumentSymbol>,
+) -> DocumentSymbol {
+    #[allow(deprecated)]
+    DocumentSymbol {
+        name: tag.name.clone(),
+        detail: tag.detail.clone(),
+        kind: super::to_lsp_symbol_kind(tag.kind),
+        tags: None,
+        deprecated: None,
+        range: positions.lsp_range(path, &tag.range),
+        selection_range: positions.lsp_range(path, &tag.name_range),
+        children: match children.is_empty() {
+            true => None,
+            false => Some(children),
+        },
+    }
+}
@@ -0,0 +1,156 @@
+use lsp_types::*;
+
+pub fn did_create_files(
+    rt: &mut crate::LspRuntime,
+    params: CreateFilesParams,
+) -> crate::Result<()> {
+    let paths: Vec<_> = params
+        .files
+        .iter()
+        .filter_map(|v| to_file_path(&v.uri))
+        .collect();
+
+    rt.indexer.refresh(paths);
+    Ok(())
+}
+
+pub fn did_rename_files(
+    rt: &mut crate::LspRuntime,
+    params: RenameFilesParams,
+) -> crate::Result<()> {
+    let mut changed = Vec::new();
+    for file in &params.files {
+        let (from, to) = match (to_file_path(&file.old_uri), to_file_path(&file.new_uri)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => continue,
+        };
+
+        // Content does not change, records move with the file.
+        rt.db.rename_path(&from, &to)?;
+
+        // A renamed file may change its language.
+        if to.is_file() {
+            match rt.parser.is_associated(&to) {
+                true => changed.push(to),
+                false => rt.db.remove_path(&to)?,
+            }
+        }
+    }
+
+    rt.indexer.refresh(changed);
+    Ok(())
+}
+
+pub fn did_delete_files(
+    rt: &mut crate::LspRuntime,
+    params: DeleteFilesParams,
+) -> crate::Result<()> {
+    for file in &params.files {
+        if let Some(path) = to_file_path(&file.uri) {
+            rt.db.remove_path(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn did_change_watched_files(
+    rt: &mut crate::LspRuntime,
+    params: DidChangeWatchedFilesParams,
+) -> crate::Result<()> {
+    // Created, changed and deleted paths are all checked on disk again.
+    let paths: Vec<_> = params
+        .changes
+        .iter()
+        .filter_map(|v| v.uri.to_file_path().ok())
+        .collect();
+
+    rt.indexer.refresh(paths);
+    Ok(())
+}
+
+/// Id of the registration of file watchers.
+const WATCHERS: &str = "syntaxforest/watchers";
+
+/// Register file watchers for all files associated with a language.
+///
+/// Watchers registered before are unregistered first, so the client always watches the
+/// current patterns.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime.
+/// + `sender` - Channel to the client.
+pub fn register_watchers(
+    rt: &mut crate::LspRuntime,
+    sender: &crossbeam_channel::Sender<lsp_server::Message>,
+) -> crate::Result<()> {
+    use lsp_types::notification::Notification;
+    use lsp_types::request::Request;
+
+    let dynamic_registration = rt
+        .capabilities
+        .workspace
+        .as_ref()
+        .and_then(|v| v.did_change_watched_files.as_ref())
+        .and_then(|v| v.dynamic_registration)
+        .unwrap_or(false);
+    if !dynamic_registration {
+        return Ok(());
+    }
+
+    let count = rt.client_watchers.unwrap_or(0);
+    if count > 0 {
+        let req = lsp_server::Request::new(
+            format!("{}/unregister/{}", WATCHERS, count).into(),
+            request::UnregisterCapability::METHOD.to_string(),
+            UnregistrationParams {
+                unregisterations: vec![Unregistration {
+                    id: WATCHERS.to_string(),
+                    method: notification::DidChangeWatchedFiles::METHOD.to_string(),
+                }],
+            },
+        );
+        sender.send(lsp_server::Message::Request(req))?;
+    }
+
+    let watchers = rt
+        .parser
+        .file_patterns()
+        .into_iter()
+        .map(|v| FileSystemWatcher {
+            glob_pattern: GlobPattern::String(v),
+            kind: None,
+        })
+        .collect();
+    let options = DidChangeWatchedFilesRegistrationOptions { watchers };
+
+    let req = lsp_server::Request::new(
+        format!("{}/register/{}", WATCHERS, count + 1).into(),
+        request::RegisterCapability::METHOD.to_string(),
+        RegistrationParams {
+            registrations: vec![Registration {
+                id: WATCHERS.to_string(),
+                method: notification::DidChangeWatchedFiles::METHOD.to_string(),
+                register_options: Some(serde_json::to_value(options)?),
+            }],
+        },
+    );
+    sender.send(lsp_server::Message::Request(req))?;
+    rt.client_watchers = Some(count + 1);
+
+    Ok(())
+}
+
+/// Convert a uri string of file operation into path.
+///
+/// # Arguments
+///
+/// + `uri` - The uri.
+///
+/// # Returns
+///
+/// + The path, or `None` if not a file uri.
+fn to_file_path(uri: &str) -> Option<std::path::PathBuf> {
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
@@ -1,14 +1,227 @@
+use lsp_types::*;
+
+use crate::db::{TagInfo, TagKind};
+use crate::syntax::SymbolAt;
+
+/// Max depth when following include directives.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A definition found for the symbol under cursor.
+struct Target {
+    uri: Url,
+    range: Range,
+    selection_range: Range,
+}
+
 pub fn goto_definition(
-    _rt: &mut crate::LspRuntime,
-    _params: lsp_types::GotoDefinitionParams,
-) -> Result<lsp_server::Response, Box<dyn std::error::Error + Sync + Send>> {
-    return Ok(lsp_server::Response {
-        id: 0.into(),
-        result: None,
-        error: Some(lsp_server::ResponseError {
-            code: lsp_server::ErrorCode::MethodNotFound as i32,
-            message: format!("method not found"),
-            data: None,
-        }),
-    });
+    rt: &mut crate::LspRuntime,
+    params: GotoDefinitionParams,
+) -> crate::Result<lsp_server::Response> {
+    let position = params.text_document_position_params.position;
+    let path = match params
+        .text_document_position_params
+        .text_document
+        .uri
+        .to_file_path()
+    {
+        Ok(v) => v,
+        Err(_) => {
+            return Ok(lsp_server::Response::new_ok(
+                0.into(),
+                serde_json::Value::Null,
+            ))
+        }
+    };
+    let source = rt.documents.read(&path)?;
+
+    let (row, col) = super::from_lsp_position(rt, &source, position);
+    let symbol = rt.parser.symbol_at(&path, &source, row, col);
+    let mut positions = super::PositionMapper::new(rt);
+
+    let (origin, targets): (_, Vec<Target>) = match symbol {
+        Some(SymbolAt::Include {
+            path: include,
+            system,
+            range,
+        }) => {
+            let dirs = include_dirs(rt);
+            let targets = crate::utils::path::resolve_include(&path, &include, system, &dirs)
+                .and_then(|v| Url::from_file_path(v).ok())
+                .map(|uri| Target {
+                    uri,
+                    range: Range::default(),
+                    selection_range: Range::default(),
+                })
+                .into_iter()
+                .collect();
+            (positions.lsp_range(&path, &range), targets)
+        }
+
+        Some(SymbolAt::Identifier { name, range, .. }) => {
+            let tags = rt.db.find_tags_by_name(&name)?;
+            let tags = pick_definitions(rt, &path, &source, tags);
+            rt.cancel.check()?;
+            let targets = tags
+                .into_iter()
+                .filter_map(|tag| {
+                    Some(Target {
+                        uri: Url::from_file_path(&tag.path).ok()?,
+                        range: positions.lsp_range(&tag.path, &tag.range),
+                        selection_range: positions.lsp_range(&tag.path, &tag.name_range),
+                    })
+                })
+                .collect();
+            (positions.lsp_range(&path, &range), targets)
+        }
+
+        None => {
+            return Ok(lsp_server::Response::new_ok(
+                0.into(),
+                serde_json::Value::Null,
+            ))
+        }
+    };
+
+    let link_support = rt
+        .capabilities
+        .text_document
+        .as_ref()
+        .and_then(|v| v.definition.as_ref())
+        .and_then(|v| v.link_support)
+        .unwrap_or(false);
+
+    let result = match link_support {
+        true => GotoDefinitionResponse::Link(
+            targets
+                .into_iter()
+                .map(|v| LocationLink {
+                    origin_selection_range: Some(origin),
+                    target_uri: v.uri,
+                    target_range: v.range,
+                    target_selection_range: v.selection_range,
+                })
+                .collect(),
+        ),
+        false => GotoDefinitionResponse::Array(
+            targets
+                .into_iter()
+                .map(|v| Location::new(v.uri, v.selection_range))
+                .collect(),
+        ),
+    };
+
+    Ok(lsp_server::Response::new_ok(0.into(), result))
+}
+
+/// Directories to search for include files, workspace folders then `includePaths`.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime.
+///
+/// # Returns
+///
+/// + List of directories.
+pub fn include_dirs(rt: &crate::LspRuntime) -> Vec<std::path::PathBuf> {
+    rt.workspace_folders
+        .iter()
+        .filter_map(|v| v.uri.to_file_path().ok())
+        .chain(rt.settings.get().include_paths.into_iter().flatten())
+        .collect()
+}
+
+/// Pick the best definitions from all tags with the same name.
+///
+/// Definitions are preferred over prototypes. A definition in the same file wins, then
+/// a definition in a header reachable from the file, then any match in workspace.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime.
+/// + `path` - The path of current file.
+/// + `source` - The content of current file.
+/// + `tags` - All tags with the same name.
+///
+/// # Returns
+///
+/// + List of definitions.
+fn pick_definitions(
+    rt: &crate::LspRuntime,
+    path: &std::path::Path,
+    source: &str,
+    tags: Vec<TagInfo>,
+) -> Vec<TagInfo> {
+    let (definitions, prototypes): (Vec<_>, Vec<_>) =
+        tags.into_iter().partition(|v| v.kind != TagKind::Prototype);
+    let candidates = match definitions.is_empty() {
+        true => prototypes,
+        false => definitions,
+    };
+
+    let same_file: Vec<_> = candidates
+        .iter()
+        .filter(|v| v.path == path)
+        .cloned()
+        .collect();
+    if !same_file.is_empty() {
+        return same_file;
+    }
+
+    let headers = reachable_headers(rt, path, source);
+    let in_headers: Vec<_> = candidates
+        .iter()
+        .filter(|v| headers.contains(&v.path))
+        .cloned()
+        .collect();
+    if !in_headers.is_empty() {
+        return in_headers;
+    }
+
+    candidates
+}
+
+/// Find all files reachable from the file through include directives.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime.
+/// + `path` - The path of the file.
+/// + `source` - The content of the file.
+///
+/// # Returns
+///
+/// + Set of reachable files.
+fn reachable_headers(
+    rt: &crate::LspRuntime,
+    path: &std::path::Path,
+    source: &str,
+) -> std::collections::HashSet<std::path::PathBuf> {
+    let dirs = include_dirs(rt);
+    let mut visited = std::collections::HashSet::new();
+    let mut pending = vec![(path.to_path_buf(), source.to_string(), 0)];
+
+    while let Some((file, content, depth)) = pending.pop() {
+        if rt.cancel.is_canceled() {
+            break;
+        }
+        if depth >= MAX_INCLUDE_DEPTH {
+            continue;
+        }
+
+        for (include, system) in rt.parser.includes(&file, &content) {
+            let header = match crate::utils::path::resolve_include(&file, &include, system, &dirs) {
+                Some(v) => v,
+                None => continue,
+            };
+            if !visited.insert(header.clone()) {
+                continue;
+            }
+
+            if let Ok(content) = rt.documents.read(&header) {
+                pending.push((header, content, depth + 1));
+            }
+        }
+    }
+
+    visited
 }
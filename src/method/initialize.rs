@@ -5,86 +5,120 @@ use crate::LspRuntime;
 pub fn initialize(
     conn: &lsp_server::Connection,
     config: &crate::LspConfig,
-) -> Result<crate::LspRuntime, Box<dyn std::error::Error + Sync + Send>> {
-    let server_capabilities = serde_json::to_value(get_server_capacity()).unwrap();
-    let initialization_params = match conn.initialize(server_capabilities) {
-        Ok(it) => it,
-        Err(e) => {
-            return Err(e.into());
-        }
-    };
+) -> crate::Result<crate::LspRuntime> {
+    let (id, initialization_params) = conn.initialize_start()?;
+    let initialization_params: lsp_types::InitializeParams =
+        serde_json::from_value(initialization_params)?;
 
-    // Create the database.
-    let conn = match &config.dbfile {
-        Some(path) => rusqlite::Connection::open(path).unwrap(),
-        None => rusqlite::Connection::open_in_memory().unwrap(),
-    };
-    let client = crate::db::SqliteClient::new(conn)?;
+    // Columns are counted in the unit the client prefers.
+    let position_encoding =
+        crate::utils::position::PositionEncoding::negotiate(&initialization_params.capabilities);
+    let server_capabilities = serde_json::to_value(get_server_capacity(position_encoding))?;
+    conn.initialize_finish(
+        id,
+        serde_json::json!({ "capabilities": server_capabilities }),
+    )?;
 
+    // Settings from all sources, invalid ones are reported once the client is ready.
+    let workspace_folders = get_workspace_folders(&initialization_params);
+    let settings = crate::settings::Settings::new();
+    let mut errors = crate::method::configuration::load(
+        &settings,
+        config,
+        &initialization_params,
+        &workspace_folders,
+    );
+
+    // Open the database.
+    let client = crate::db::SqliteClient::open(settings.get().database.as_deref())?;
+
+    let encodings = crate::encoding::Encodings::new();
     let mut rt = LspRuntime {
-        workspace_folders: vec![],
+        workspace_folders,
+        capabilities: ClientCapabilities::default(),
+        position_encoding,
         db: client,
         parser: crate::syntax::SyntaxParser::new(),
+        documents: crate::document::DocumentStore::new(encodings.clone()),
+        encodings,
+        settings,
+        indexer: crate::indexer::Indexer::new(),
+        watcher: crate::watcher::Watcher::new(),
+        client_process_id: None,
+        client_watchers: None,
+        cancel: crate::request::CancelToken::new(),
     };
 
     // Parse the initialization parameters.
-    let initialization_params: lsp_types::InitializeParams =
-        serde_json::from_value(initialization_params)?;
-    copy_workspace_folder(&mut rt, &initialization_params);
+    rt.capabilities = initialization_params.capabilities.clone();
+    rt.client_process_id = initialization_params
+        .process_id
+        .or(config.client_process_id);
 
-    let mut file_list = Vec::new();
-    for folder in &rt.workspace_folders {
-        let file_path = folder.uri.to_file_path().unwrap();
-        let mut cwd_file_list = crate::utils::path::walk_with_gitignore(file_path)?;
-        file_list.append(&mut cwd_file_list);
-    }
-    let file_list = rt.parser.filter_file_suffix(&file_list);
+    errors.append(&mut crate::method::configuration::apply(&rt));
+    crate::method::configuration::report(&conn.sender, errors)?;
 
-    rt.db.startup_scan(&file_list)?;
+    // Watch files by server if asked, otherwise by client.
+    match rt.settings.get().watch.unwrap_or(false) {
+        true => {
+            if let Err(e) = rt.watcher.start(&rt) {
+                tracing::error!("start file watcher failed: {}", e);
+            }
+        }
+        false => crate::method::file_operations::register_watchers(&mut rt, &conn.sender)?,
+    }
 
-    trigger_tree_sitter(&mut rt);
+    // Index in background, requests are served with partial data meanwhile.
+    rt.indexer.start(&rt, conn.sender.clone());
 
     Ok(rt)
 }
 
-fn trigger_tree_sitter(rt: &mut crate::LspRuntime) {
-    let files = rt.db.pending_analysis().unwrap();
-
-    for file in files {
-        rt.parser.parser(&file.path, &rt.db).unwrap();
-    }
-}
-
-/// Safe workspace folders from client initialize params.
+/// Get workspace folders from client initialize params.
 ///
 /// # Arguments
 ///
-/// + `dst` - A mut reference to Runtime.
 /// + `src` - Reference to InitializeParams
-fn copy_workspace_folder(dst: &mut crate::LspRuntime, src: &InitializeParams) {
+///
+/// # Returns
+///
+/// + The workspace folders.
+fn get_workspace_folders(src: &InitializeParams) -> Vec<WorkspaceFolder> {
+    if let Some(value) = &src.workspace_folders {
+        return value.clone();
+    }
+
     match &src.root_uri {
-        Some(value) => dst.workspace_folders.push(WorkspaceFolder {
+        Some(value) => vec![WorkspaceFolder {
             name: String::from(""),
             uri: value.clone(),
-        }),
-        None => (),
-    };
-
-    match &src.workspace_folders {
-        Some(value) => dst.workspace_folders = value.clone(),
-        None => (),
-    };
+        }],
+        None => vec![],
+    }
 }
 
 /// Get the default server capabilities.
 ///
+/// # Arguments
+///
+/// + `position_encoding` - The unit of columns negotiated with the client.
+///
 /// Returns
 ///
 /// + `ServerCapabilities` - The default server capabilities.
-fn get_server_capacity() -> ServerCapabilities {
-    return ServerCapabilities {
-        position_encoding: Some(PositionEncodingKind::UTF8),
-        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+fn get_server_capacity(
+    position_encoding: crate::utils::position::PositionEncoding,
+) -> ServerCapabilities {
+    ServerCapabilities {
+        position_encoding: Some(position_encoding.kind()),
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::INCREMENTAL),
+                save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                ..Default::default()
+            },
+        )),
         completion_provider: Some(CompletionOptions {
             ..Default::default()
         }),
@@ -111,30 +145,32 @@ fn get_server_capacity() -> ServerCapabilities {
         workspace: Some(WorkspaceServerCapabilities {
             workspace_folders: Some(WorkspaceFoldersServerCapabilities {
                 supported: Some(true),
-                change_notifications: Some(OneOf::Left(false)),
+                change_notifications: Some(OneOf::Left(true)),
             }),
             file_operations: Some(WorkspaceFileOperationsServerCapabilities {
-                did_rename: Some(FileOperationRegistrationOptions {
-                    filters: vec![FileOperationFilter {
-                        scheme: Option::None,
-                        pattern: FileOperationPattern {
-                            glob: String::from("*"),
-                            ..Default::default()
-                        },
-                    }],
-                }),
-                did_delete: Some(FileOperationRegistrationOptions {
-                    filters: vec![FileOperationFilter {
-                        scheme: Option::None,
-                        pattern: FileOperationPattern {
-                            glob: String::from("*"),
-                            ..Default::default()
-                        },
-                    }],
-                }),
+                did_create: Some(file_operation_options()),
+                did_rename: Some(file_operation_options()),
+                did_delete: Some(file_operation_options()),
                 ..Default::default()
             }),
         }),
         ..ServerCapabilities::default()
-    };
+    }
+}
+
+/// Get the filters of file operations the server is interested in.
+///
+/// Returns
+///
+/// + `FileOperationRegistrationOptions` - All files and folders.
+fn file_operation_options() -> FileOperationRegistrationOptions {
+    FileOperationRegistrationOptions {
+        filters: vec![FileOperationFilter {
+            scheme: Some(String::from("file")),
+            pattern: FileOperationPattern {
+                glob: String::from("**/*"),
+                ..Default::default()
+            },
+        }],
+    }
 }
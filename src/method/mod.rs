@@ -1,29 +1,185 @@
+pub mod configuration;
+pub mod document_symbol;
+pub mod file_operations;
 pub mod goto_definition;
 pub mod initialize;
+pub mod references;
 pub mod shutdown;
+pub mod text_document_sync;
+pub mod workspace_folders;
+pub mod workspace_symbol;
 
-/// TreeSitter node kind.
-#[derive(Debug, Copy, Clone)]
-pub enum TreeSitterNodeKind {
-    PreprocFunctionDef = 158,
+/// Convert positions between the database and the client.
+///
+/// Columns in database are in bytes, they are converted into the encoding negotiated
+/// with the client. Files are read on demand, only the lines of converted positions are
+/// scanned.
+pub struct PositionMapper<'a> {
+    rt: &'a crate::LspRuntime,
+
+    /// Files read so far, `None` if a file can not be read.
+    files: std::collections::HashMap<std::path::PathBuf, Option<FileLines>>,
+}
+
+/// The content of a file, with the offsets of its lines.
+struct FileLines {
+    text: String,
+
+    /// Byte offset of the start of each line.
+    starts: Vec<usize>,
+
+    /// Whether the file was found shorter than indexed.
+    stale: bool,
 }
 
-impl std::convert::TryFrom<u16> for TreeSitterNodeKind {
-    type Error = u16;
+impl FileLines {
+    fn new(text: String) -> FileLines {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        FileLines {
+            text,
+            starts,
+            stale: false,
+        }
+    }
+
+    /// Get a line without its line break.
+    fn line(&self, row: usize) -> Option<&str> {
+        let beg = *self.starts.get(row)?;
+        let end = self.starts.get(row + 1).map_or(self.text.len(), |v| v - 1);
+        Some(&self.text[beg..end])
+    }
+}
+
+impl<'a> PositionMapper<'a> {
+    pub fn new(rt: &'a crate::LspRuntime) -> PositionMapper<'a> {
+        PositionMapper {
+            rt,
+            files: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Convert range in database to LSP range.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file the range is in.
+    /// + `range` - The range in database.
+    ///
+    /// # Returns
+    ///
+    /// + LSP range.
+    pub fn lsp_range(
+        &mut self,
+        path: &std::path::Path,
+        range: &crate::db::TagRange,
+    ) -> lsp_types::Range {
+        lsp_types::Range {
+            start: self.lsp_position(path, range.beg_row, range.beg_col),
+            end: self.lsp_position(path, range.end_row, range.end_col),
+        }
+    }
 
-    /// Convert from `u16` to `TreeSitterNodeKind`.
+    /// Convert position in database to LSP position.
     ///
     /// # Arguments
     ///
-    /// + `value` - `u16` to convert.
+    /// + `path` - The path of the file the position is in.
+    /// + `row` - The row.
+    /// + `col` - The column in bytes.
     ///
     /// # Returns
     ///
-    /// + `TreeSitterNodeKind` converted from `u16`.
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        match value {
-            158 => Ok(TreeSitterNodeKind::PreprocFunctionDef),
-            unmatched => Err(unmatched),
+    /// + LSP position, clamped to the end of the file if it changed since indexed.
+    fn lsp_position(&mut self, path: &std::path::Path, row: i64, col: i64) -> lsp_types::Position {
+        let encoding = self.rt.position_encoding;
+        if encoding == crate::utils::position::PositionEncoding::Utf8 {
+            return lsp_types::Position::new(row as u32, col as u32);
+        }
+
+        let documents = &self.rt.documents;
+        let file =
+            self.files
+                .entry(path.to_path_buf())
+                .or_insert_with(|| match documents.read(path) {
+                    Ok(v) => Some(FileLines::new(v)),
+                    Err(e) => {
+                        tracing::warn!("read {} failed: {}", path.display(), e);
+                        None
+                    }
+                });
+
+        // Nothing to convert by, the column is the best guess.
+        let Some(file) = file else {
+            return lsp_types::Position::new(row as u32, col as u32);
+        };
+
+        if let Some(line) = file.line(row as usize) {
+            return lsp_types::Position::new(
+                row as u32,
+                encoding.encode_column(line, col as usize),
+            );
         }
+
+        // The file is shorter than indexed, point to its end until it is indexed again.
+        if !file.stale {
+            tracing::warn!("{} changed since indexed", path.display());
+            file.stale = true;
+        }
+        let last = file.starts.len() - 1;
+        let character = file
+            .line(last)
+            .map_or(0, |v| encoding.encode_column(v, v.len()));
+        lsp_types::Position::new(last as u32, character)
+    }
+}
+
+/// Convert LSP position to row and column in bytes.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime.
+/// + `source` - The content of the file.
+/// + `position` - LSP position.
+///
+/// # Returns
+///
+/// + The row and the column in bytes.
+pub fn from_lsp_position(
+    rt: &crate::LspRuntime,
+    source: &str,
+    position: lsp_types::Position,
+) -> (usize, usize) {
+    let row = position.line as usize;
+    let col = match source.split('\n').nth(row) {
+        Some(line) => rt.position_encoding.decode_column(line, position.character),
+        None => position.character as usize,
+    };
+    (row, col)
+}
+
+/// Convert tag kind to LSP symbol kind.
+///
+/// # Arguments
+///
+/// + `kind` - The tag kind.
+///
+/// # Returns
+///
+/// + LSP symbol kind.
+pub fn to_lsp_symbol_kind(kind: crate::db::TagKind) -> lsp_types::SymbolKind {
+    use crate::db::TagKind;
+    use lsp_types::SymbolKind;
+
+    match kind {
+        TagKind::Function | TagKind::Prototype | TagKind::FunctionMacro => SymbolKind::FUNCTION,
+        TagKind::Struct | TagKind::Union => SymbolKind::STRUCT,
+        TagKind::Enum => SymbolKind::ENUM,
+        TagKind::Typedef => SymbolKind::CLASS,
+        TagKind::Enumerator => SymbolKind::ENUM_MEMBER,
+        TagKind::Variable => SymbolKind::VARIABLE,
+        TagKind::Macro => SymbolKind::CONSTANT,
+        TagKind::Field => SymbolKind::FIELD,
     }
 }
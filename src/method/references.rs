@@ -0,0 +1,92 @@
+use lsp_types::*;
+
+use crate::syntax::SymbolAt;
+
+/// Number of locations sent in one partial result.
+const PARTIAL_RESULT_BATCH: usize = 256;
+
+pub fn references(
+    rt: &mut crate::LspRuntime,
+    sender: &crossbeam_channel::Sender<lsp_server::Message>,
+    params: ReferenceParams,
+) -> crate::Result<lsp_server::Response> {
+    let position = params.text_document_position.position;
+    let path = match params
+        .text_document_position
+        .text_document
+        .uri
+        .to_file_path()
+    {
+        Ok(v) => v,
+        Err(_) => {
+            return Ok(lsp_server::Response::new_ok(
+                0.into(),
+                serde_json::Value::Null,
+            ))
+        }
+    };
+    let source = rt.documents.read(&path)?;
+
+    let (row, col) = super::from_lsp_position(rt, &source, position);
+    let (name, local) = match rt.parser.symbol_at(&path, &source, row, col) {
+        Some(SymbolAt::Identifier { name, local, .. }) => (name, local),
+        _ => {
+            return Ok(lsp_server::Response::new_ok(
+                0.into(),
+                serde_json::Value::Null,
+            ))
+        }
+    };
+
+    // A local variable hides tags of the same name, only references in its scope count.
+    let mut xrefs = rt.db.find_xrefs_by_name(&name, &rt.cancel)?;
+    let tags = match &local {
+        Some(local) => {
+            xrefs.retain(|v| v.path == path && local.scope.contains(&v.range));
+            Vec::new()
+        }
+        None => rt.db.find_tags_by_name(&name)?,
+    };
+    rt.cancel.check()?;
+
+    let mut positions = super::PositionMapper::new(rt);
+    let mut locations = Vec::new();
+    if params.context.include_declaration {
+        if let (Some(local), Ok(uri)) = (&local, Url::from_file_path(&path)) {
+            locations.push(Location::new(uri, positions.lsp_range(&path, &local.range)));
+        }
+        for tag in &tags {
+            if let Ok(uri) = Url::from_file_path(&tag.path) {
+                locations.push(Location::new(
+                    uri,
+                    positions.lsp_range(&tag.path, &tag.name_range),
+                ));
+            }
+        }
+    }
+    for xref in &xrefs {
+        if let Ok(uri) = Url::from_file_path(&xref.path) {
+            locations.push(Location::new(
+                uri,
+                positions.lsp_range(&xref.path, &xref.range),
+            ));
+        }
+    }
+    locations.sort_by(|a, b| (a.uri.as_str(), a.range.start).cmp(&(b.uri.as_str(), b.range.start)));
+    locations.dedup();
+
+    // Stream results if client asks for partial results, the final response is empty.
+    if let Some(token) = params.partial_result_params.partial_result_token {
+        for batch in locations.chunks(PARTIAL_RESULT_BATCH) {
+            rt.cancel.check()?;
+            let nfy = lsp_server::Notification::new(
+                "$/progress".to_string(),
+                serde_json::json!({ "token": token, "value": batch }),
+            );
+            sender.send(lsp_server::Message::Notification(nfy))?;
+        }
+        locations.clear();
+    }
+
+    Ok(lsp_server::Response::new_ok(0.into(), locations))
+}
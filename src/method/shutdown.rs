@@ -1,5 +1,6 @@
-pub fn shutdown(
-    _rt: &mut crate::LspRuntime,
-) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+pub fn shutdown(rt: &mut crate::LspRuntime) -> crate::Result<()> {
+    rt.watcher.stop();
+    rt.indexer.stop();
+    rt.db.close()?;
     Ok(())
 }
@@ -0,0 +1,202 @@
+use lsp_types::*;
+
+pub fn did_open(
+    rt: &mut crate::LspRuntime,
+    params: DidOpenTextDocumentParams,
+) -> crate::Result<()> {
+    let doc = params.text_document;
+    let path = match doc.uri.to_file_path() {
+        Ok(v) => v,
+        Err(_) => return Ok(()),
+    };
+
+    // The client knows the language better than the file name.
+    rt.parser.set_language_id(&path, Some(&doc.language_id));
+    rt.documents.open(
+        &path,
+        crate::document::Document {
+            language_id: doc.language_id,
+            version: doc.version,
+            text: doc.text,
+            tree: None,
+        },
+    );
+
+    reindex(rt, &path)
+}
+
+pub fn did_change(
+    rt: &mut crate::LspRuntime,
+    params: DidChangeTextDocumentParams,
+) -> crate::Result<()> {
+    let path = match params.text_document.uri.to_file_path() {
+        Ok(v) => v,
+        Err(_) => return Ok(()),
+    };
+
+    let doc = match rt.documents.get(&path) {
+        Some(v) if v.version <= params.text_document.version => v,
+        _ => return Ok(()),
+    };
+
+    // Changes apply in order, each one to the result of the previous. The text follows
+    // the client even if parsing fails, the tree is dropped and built again from scratch.
+    let mut text = doc.text;
+    let mut tree = doc.tree;
+    let mut error = None;
+    for change in params.content_changes {
+        let ret = match change.range {
+            None => {
+                text = change.text;
+                rt.parser.parse_source(&path, &text, &rt.db)
+            }
+            Some(range) => {
+                let edit = apply_change(&mut text, range, &change.text, rt.position_encoding);
+                match tree {
+                    Some(mut old) => {
+                        old.edit(&edit);
+                        rt.parser
+                            .parse_incremental(&path, &text, &old, &edit, &rt.db)
+                    }
+                    None => rt.parser.parse_source(&path, &text, &rt.db),
+                }
+            }
+        };
+        tree = ret.unwrap_or_else(|e| {
+            error.get_or_insert(e);
+            None
+        });
+    }
+
+    rt.documents
+        .update(&path, params.text_document.version, text, tree);
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+pub fn did_save(
+    rt: &mut crate::LspRuntime,
+    params: DidSaveTextDocumentParams,
+) -> crate::Result<()> {
+    let path = match params.text_document.uri.to_file_path() {
+        Ok(v) => v,
+        Err(_) => return Ok(()),
+    };
+
+    reindex(rt, &path)
+}
+
+pub fn did_close(
+    rt: &mut crate::LspRuntime,
+    params: DidCloseTextDocumentParams,
+) -> crate::Result<()> {
+    let path = match params.text_document.uri.to_file_path() {
+        Ok(v) => v,
+        Err(_) => return Ok(()),
+    };
+
+    // Unsaved changes are dropped, index the file on disk again.
+    rt.documents.close(&path);
+    rt.parser.set_language_id(&path, None);
+    if path.is_file() {
+        reindex(rt, &path)?;
+    }
+
+    Ok(())
+}
+
+/// Apply a ranged change to the text.
+///
+/// # Arguments
+///
+/// + `text` - The text to change.
+/// + `range` - The range to replace.
+/// + `new_text` - The replacement.
+/// + `encoding` - The unit of columns in `range`.
+///
+/// # Returns
+///
+/// + The edit for the syntax tree.
+fn apply_change(
+    text: &mut String,
+    range: Range,
+    new_text: &str,
+    encoding: crate::utils::position::PositionEncoding,
+) -> tree_sitter::InputEdit {
+    let start_byte = byte_offset(text, range.start, encoding);
+    let old_end_byte = byte_offset(text, range.end, encoding).max(start_byte);
+    let start_position = point_at(text, start_byte);
+    let old_end_position = point_at(text, old_end_byte);
+
+    text.replace_range(start_byte..old_end_byte, new_text);
+    let new_end_byte = start_byte + new_text.len();
+
+    tree_sitter::InputEdit {
+        start_byte,
+        old_end_byte,
+        new_end_byte,
+        start_position,
+        old_end_position,
+        new_end_position: point_at(text, new_end_byte),
+    }
+}
+
+/// Convert a position into byte offset, clamped to the line and the text.
+///
+/// # Arguments
+///
+/// + `text` - The text.
+/// + `position` - The position.
+/// + `encoding` - The unit of the column.
+///
+/// # Returns
+///
+/// + The byte offset.
+fn byte_offset(
+    text: &str,
+    position: Position,
+    encoding: crate::utils::position::PositionEncoding,
+) -> usize {
+    let mut offset = 0;
+    for _ in 0..position.line {
+        match text[offset..].find('\n') {
+            Some(v) => offset += v + 1,
+            None => return text.len(),
+        }
+    }
+
+    let line_end = text[offset..].find('\n').map_or(text.len(), |v| offset + v);
+    offset + encoding.decode_column(&text[offset..line_end], position.character)
+}
+
+/// Convert a byte offset into point.
+///
+/// # Arguments
+///
+/// + `text` - The text.
+/// + `offset` - The byte offset.
+///
+/// # Returns
+///
+/// + The point.
+fn point_at(text: &str, offset: usize) -> tree_sitter::Point {
+    let before = &text[..offset];
+    let row = before.matches('\n').count();
+    let column = offset - before.rfind('\n').map_or(0, |v| v + 1);
+    tree_sitter::Point { row, column }
+}
+
+/// Index the file again from its current content.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime.
+/// + `path` - The path of the file.
+fn reindex(rt: &mut crate::LspRuntime, path: &std::path::Path) -> crate::Result<()> {
+    let content = rt.documents.read(path)?;
+    let tree = rt.parser.parse_source(path, &content, &rt.db)?;
+    rt.documents.set_tree(path, tree);
+    Ok(())
+}
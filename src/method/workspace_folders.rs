@@ -0,0 +1,108 @@
+use lsp_types::*;
+
+pub fn did_change_workspace_folders(
+    rt: &mut crate::LspRuntime,
+    params: DidChangeWorkspaceFoldersParams,
+) -> crate::Result<()> {
+    for folder in &params.event.removed {
+        rt.workspace_folders.retain(|v| v.uri != folder.uri);
+
+        let path = match folder.uri.to_file_path() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        // Files still in another folder are kept.
+        let roots = roots(rt);
+        if roots.iter().any(|v| path.starts_with(v)) {
+            continue;
+        }
+
+        tracing::info!("remove workspace folder {}", path.display());
+        if let Err(e) = rt.watcher.remove_folder(&path) {
+            tracing::warn!("unwatch {} failed: {}", path.display(), e);
+        }
+        rt.indexer.remove_folder(path.clone());
+
+        // Folders inside the removed one are indexed again.
+        for root in roots.into_iter().filter(|v| v.starts_with(&path)) {
+            add_folder(rt, root);
+        }
+    }
+
+    for folder in &params.event.added {
+        if rt.workspace_folders.iter().any(|v| v.uri == folder.uri) {
+            continue;
+        }
+
+        let path = match folder.uri.to_file_path() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        // Files already in another folder are indexed.
+        let covered = roots(rt).iter().any(|v| path.starts_with(v));
+        rt.workspace_folders.push(folder.clone());
+        if !covered {
+            tracing::info!("add workspace folder {}", path.display());
+            add_folder(rt, path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the name of the workspace folder a file belongs to.
+///
+/// Only multi-root workspaces have names, the innermost folder wins.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime.
+/// + `path` - The path of the file.
+///
+/// # Returns
+///
+/// + The name of the folder.
+pub fn folder_name(rt: &crate::LspRuntime, path: &std::path::Path) -> Option<String> {
+    if rt.workspace_folders.len() < 2 {
+        return None;
+    }
+
+    let (folder, root) = rt
+        .workspace_folders
+        .iter()
+        .filter_map(|v| Some((v, v.uri.to_file_path().ok()?)))
+        .filter(|(_, root)| path.starts_with(root))
+        .max_by_key(|(_, root)| root.components().count())?;
+
+    match folder.name.is_empty() {
+        true => root.file_name().map(|v| v.to_string_lossy().into_owned()),
+        false => Some(folder.name.clone()),
+    }
+}
+
+/// Index and watch a folder.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime.
+/// + `path` - The path of the folder.
+fn add_folder(rt: &crate::LspRuntime, path: std::path::PathBuf) {
+    if let Err(e) = rt.watcher.add_folder(&path) {
+        tracing::warn!("watch {} failed: {}", path.display(), e);
+    }
+    rt.indexer.add_folder(path);
+}
+
+/// Get paths of all workspace folders.
+///
+/// # Arguments
+///
+/// + `rt` - The runtime.
+fn roots(rt: &crate::LspRuntime) -> Vec<std::path::PathBuf> {
+    rt.workspace_folders
+        .iter()
+        .filter_map(|v| v.uri.to_file_path().ok())
+        .collect()
+}
@@ -0,0 +1,122 @@
+use lsp_types::*;
+
+use crate::db::{TagInfo, TagKind};
+
+/// Max number of candidates read from database for each source.
+const MAX_CANDIDATES: usize = 20000;
+
+/// Max number of symbols returned.
+const MAX_RESULTS: usize = 256;
+
+pub fn workspace_symbol(
+    rt: &mut crate::LspRuntime,
+    params: WorkspaceSymbolParams,
+) -> crate::Result<lsp_server::Response> {
+    let query = params.query.trim();
+    if query.is_empty() {
+        return Ok(lsp_server::Response::new_ok(
+            0.into(),
+            Vec::<SymbolInformation>::new(),
+        ));
+    }
+
+    let near = rt.documents.paths();
+    let mut ranked: Vec<(i64, TagInfo)> = rt
+        .db
+        .search_tags(query, MAX_CANDIDATES, &rt.cancel)?
+        .into_iter()
+        .filter_map(|tag| {
+            let score = crate::utils::fuzzy::score(query, &tag.name)?;
+            Some((
+                score + kind_weight(tag.kind) + proximity(&tag.path, &near),
+                tag,
+            ))
+        })
+        .collect();
+    rt.cancel.check()?;
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.name.cmp(&b.1.name)));
+    ranked.truncate(MAX_RESULTS);
+
+    let mut positions = super::PositionMapper::new(rt);
+    let result: Vec<SymbolInformation> = ranked
+        .into_iter()
+        .filter_map(|(_, tag)| {
+            let container_name = super::workspace_folders::folder_name(rt, &tag.path);
+            #[allow(deprecated)]
+            Some(SymbolInformation {
+                name: tag.name,
+                kind: super::to_lsp_symbol_kind(tag.kind),
+                tags: None,
+                deprecated: None,
+                location: Location::new(
+                    Url::from_file_path(&tag.path).ok()?,
+                    positions.lsp_range(&tag.path, &tag.name_range),
+                ),
+                container_name,
+            })
+        })
+        .collect();
+
+    Ok(lsp_server::Response::new_ok(
+        0.into(),
+        WorkspaceSymbolResponse::Flat(result),
+    ))
+}
+
+/// Rank bonus of tag kind, definitions come first.
+///
+/// # Arguments
+///
+/// + `kind` - The tag kind.
+///
+/// # Returns
+///
+/// + The bonus.
+fn kind_weight(kind: TagKind) -> i64 {
+    match kind {
+        TagKind::Function
+        | TagKind::Struct
+        | TagKind::Union
+        | TagKind::Enum
+        | TagKind::Typedef
+        | TagKind::Macro
+        | TagKind::FunctionMacro => 50,
+        TagKind::Variable | TagKind::Enumerator => 30,
+        TagKind::Prototype => 10,
+        TagKind::Field => 0,
+    }
+}
+
+/// Rank bonus of the distance between a file and the files user is working on.
+///
+/// # Arguments
+///
+/// + `path` - The path of the file.
+/// + `near` - The files user is working on.
+///
+/// # Returns
+///
+/// + The bonus.
+fn proximity(path: &std::path::Path, near: &[std::path::PathBuf]) -> i64 {
+    near.iter()
+        .map(|v| {
+            if v == path {
+                return 40;
+            }
+            let common = v
+                .parent()
+                .into_iter()
+                .flat_map(|v| v.components())
+                .zip(path.components())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let depth = path.components().count().saturating_sub(1);
+            match depth.saturating_sub(common) {
+                0 => 20,
+                1 => 10,
+                _ => 0,
+            }
+        })
+        .max()
+        .unwrap_or(0)
+}
@@ -0,0 +1,83 @@
+/// Time between checks of the client process.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Watch the client process.
+///
+/// # Arguments
+///
+/// + `pid` - The process id of the client.
+///
+/// # Returns
+///
+/// + Channel that is disconnected once the client process exits.
+#[cfg(unix)]
+pub fn watch(pid: u32) -> crossbeam_channel::Receiver<()> {
+    let (sender, receiver) = crossbeam_channel::bounded::<()>(0);
+
+    std::thread::spawn(move || {
+        while is_alive(pid) {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        tracing::warn!("client process {} exited", pid);
+        drop(sender);
+    });
+
+    receiver
+}
+
+#[cfg(not(unix))]
+pub fn watch(pid: u32) -> crossbeam_channel::Receiver<()> {
+    tracing::warn!(
+        "watching client process {} is not supported on this platform",
+        pid
+    );
+    crossbeam_channel::never()
+}
+
+/// Check whether a process is running.
+///
+/// A process that exited but is not reaped by its parent yet still exists. It is seen as
+/// exited on Linux only, other platforms wait until the parent reaps it.
+///
+/// # Arguments
+///
+/// + `pid` - The process id.
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    let pid = match libc::pid_t::try_from(pid) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+
+    // SAFETY: Signal 0 only checks whether the process exists.
+    let ret = unsafe { libc::kill(pid, 0) };
+    if ret != 0 && std::io::Error::last_os_error().raw_os_error() != Some(libc::EPERM) {
+        return false;
+    }
+
+    !is_zombie(pid)
+}
+
+/// Check whether a process exited and waits to be reaped.
+///
+/// # Arguments
+///
+/// + `pid` - The process id.
+#[cfg(target_os = "linux")]
+fn is_zombie(pid: libc::pid_t) -> bool {
+    // The state follows the name, which is in parentheses and may contain them.
+    let stat = match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    let state = stat
+        .rfind(')')
+        .and_then(|i| stat[i + 1..].split_whitespace().next());
+    matches!(state, Some("Z" | "X"))
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_zombie(_pid: libc::pid_t) -> bool {
+    false
+}
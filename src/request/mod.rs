@@ -0,0 +1,149 @@
+/// The request is running.
+const RUNNING: u8 = 0;
+
+/// The client canceled the request.
+const CANCELED: u8 = 1;
+
+/// A document changed while the request was running, so its result is stale.
+const MODIFIED: u8 = 2;
+
+/// Cancellation of a request, shared by the dispatcher and the handler.
+#[derive(Debug, Default, Clone)]
+pub struct CancelToken {
+    state: std::sync::Arc<std::sync::atomic::AtomicU8>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Check whether the request is canceled, or its result is stale.
+    pub fn is_canceled(&self) -> bool {
+        self.state.load(std::sync::atomic::Ordering::Relaxed) != RUNNING
+    }
+
+    /// Stop handling the request if it is canceled.
+    ///
+    /// # Returns
+    ///
+    /// + `Error::Canceled` if the request is canceled.
+    pub fn check(&self) -> crate::Result<()> {
+        match self.is_canceled() {
+            true => Err(crate::Error::Canceled),
+            false => Ok(()),
+        }
+    }
+
+    /// Mark the request with a reason, the first reason wins.
+    ///
+    /// # Arguments
+    ///
+    /// + `state` - `CANCELED` or `MODIFIED`.
+    fn set(&self, state: u8) {
+        self.state
+            .compare_exchange(
+                RUNNING,
+                state,
+                std::sync::atomic::Ordering::Relaxed,
+                std::sync::atomic::Ordering::Relaxed,
+            )
+            .ok();
+    }
+}
+
+/// Requests running on a pool of worker threads.
+///
+/// Requests are answered in the order they finish, so a slow request does not block
+/// the others.
+#[derive(Debug, Clone)]
+pub struct Requests {
+    /// Tokens of running requests. Responses are sent with the lock held, so no
+    /// result is sent once the request is marked.
+    pending: std::sync::Arc<
+        std::sync::Mutex<std::collections::HashMap<lsp_server::RequestId, CancelToken>>,
+    >,
+
+    /// The worker threads.
+    pool: std::sync::Arc<rayon::ThreadPool>,
+}
+
+impl Requests {
+    pub fn new() -> crate::Result<Requests> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .thread_name(|i| format!("request-{}", i))
+            .build()?;
+
+        Ok(Requests {
+            pending: Default::default(),
+            pool: std::sync::Arc::new(pool),
+        })
+    }
+
+    /// Handle a request on a worker thread, and send the response.
+    ///
+    /// # Arguments
+    ///
+    /// + `id` - The id of the request.
+    /// + `sender` - Channel to the client.
+    /// + `f` - The handler, the id of the response it returns is overwritten.
+    pub fn spawn<F>(
+        &self,
+        id: lsp_server::RequestId,
+        sender: crossbeam_channel::Sender<lsp_server::Message>,
+        f: F,
+    ) where
+        F: FnOnce(&CancelToken) -> lsp_server::Response + Send + 'static,
+    {
+        let token = CancelToken::new();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(id.clone(), token.clone());
+
+        let pending = self.pending.clone();
+        self.pool.spawn(move || {
+            // Canceled while waiting in queue.
+            let rsp = match token.is_canceled() {
+                true => lsp_server::Response::new_ok(0.into(), serde_json::Value::Null),
+                false => f(&token),
+            };
+
+            let mut pending = pending.lock().unwrap();
+            pending.remove(&id);
+            let mut rsp = match token.state.load(std::sync::atomic::Ordering::Relaxed) {
+                CANCELED => lsp_server::Response::new_err(
+                    0.into(),
+                    lsp_server::ErrorCode::RequestCanceled as i32,
+                    String::from("request canceled"),
+                ),
+                MODIFIED => lsp_server::Response::new_err(
+                    0.into(),
+                    lsp_server::ErrorCode::ContentModified as i32,
+                    String::from("content modified"),
+                ),
+                _ => rsp,
+            };
+            rsp.id = id;
+            sender.send(lsp_server::Message::Response(rsp)).ok();
+        });
+    }
+
+    /// Cancel a request by the client.
+    ///
+    /// # Arguments
+    ///
+    /// + `id` - The id of the request.
+    pub fn cancel(&self, id: &lsp_server::RequestId) {
+        if let Some(token) = self.pending.lock().unwrap().get(id) {
+            token.set(CANCELED);
+        }
+    }
+
+    /// Mark all running requests stale, called before an edit is applied.
+    pub fn modified(&self) {
+        for token in self.pending.lock().unwrap().values() {
+            token.set(MODIFIED);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+/// Name of the configuration file in workspace folders.
+pub const WORKSPACE_FILE: &str = ".syntaxforest.toml";
+
+/// Where a layer of settings comes from, from low to high priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    /// The user configuration file.
+    UserFile,
+
+    /// `.syntaxforest.toml` in workspace folders.
+    WorkspaceFile,
+
+    /// The command line.
+    CommandLine,
+
+    /// `initializationOptions` of the initialize request.
+    InitializationOptions,
+
+    /// `workspace/didChangeConfiguration` notifications.
+    Client,
+}
+
+/// One layer of settings, unset values are taken from lower layers.
+#[derive(Debug, Default, Clone, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Layer {
+    /// Glob patterns of files and their languages, checked before the builtin ones.
+    pub associations: Option<std::collections::BTreeMap<String, String>>,
+
+    /// Glob patterns of files that are not indexed. Relative patterns match anywhere in
+    /// the path.
+    pub exclude: Option<Vec<String>>,
+
+    /// Directories searched for include files after workspace folders.
+    pub include_paths: Option<Vec<std::path::PathBuf>>,
+
+    /// Files larger than this in bytes are not indexed.
+    pub max_file_size: Option<u64>,
+
+    /// Number of indexing threads, `0` for one per CPU.
+    pub threads: Option<usize>,
+
+    /// Path of the database, in memory if not set.
+    pub database: Option<std::path::PathBuf>,
+
+    /// Whether the server watches workspace folders itself.
+    pub watch: Option<bool>,
+
+    /// Encoding of files that are not UTF-8.
+    pub encoding: Option<String>,
+
+    /// Encodings of files matching glob patterns.
+    pub encoding_overrides: Option<std::collections::BTreeMap<String, String>>,
+}
+
+impl Layer {
+    /// Parse a layer from JSON sent by the client.
+    ///
+    /// # Arguments
+    ///
+    /// + `value` - The settings, `null` for an empty layer.
+    /// + `base` - Relative paths are resolved against it.
+    ///
+    /// # Returns
+    ///
+    /// + The layer.
+    pub fn from_json(
+        value: serde_json::Value,
+        base: Option<&std::path::Path>,
+    ) -> crate::Result<Layer> {
+        if value.is_null() {
+            return Ok(Layer::default());
+        }
+
+        let mut layer: Layer = serde_json::from_value(value)
+            .map_err(|e| crate::Error::Config(format!("invalid settings: {}", e)))?;
+        if let Some(base) = base {
+            layer.resolve_paths(base);
+        }
+        Ok(layer)
+    }
+
+    /// Read a layer from a TOML file, relative paths are resolved against its directory.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file.
+    ///
+    /// # Returns
+    ///
+    /// + The layer, or `None` if the file does not exist.
+    pub fn from_file(path: &std::path::Path) -> crate::Result<Option<Layer>> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut layer: Layer = toml::from_str(&content)
+            .map_err(|e| crate::Error::Config(format!("{}: {}", path.display(), e)))?;
+        if let Some(base) = path.parent() {
+            layer.resolve_paths(base);
+        }
+        Ok(Some(layer))
+    }
+
+    /// Build a layer from the command line.
+    ///
+    /// # Arguments
+    ///
+    /// + `config` - The LSP configuration.
+    ///
+    /// # Returns
+    ///
+    /// + The layer.
+    pub fn from_command_line(config: &crate::LspConfig) -> Layer {
+        Layer {
+            database: config.dbfile.as_ref().map(std::path::PathBuf::from),
+            watch: config.watch.then_some(true),
+            encoding: config.encoding.clone(),
+            encoding_overrides: match config.encoding_override.is_empty() {
+                true => None,
+                false => Some(config.encoding_override.iter().cloned().collect()),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Take values set in another layer.
+    ///
+    /// # Arguments
+    ///
+    /// + `other` - The layer with higher priority.
+    pub fn merge(&mut self, other: Layer) {
+        self.associations = other.associations.or(self.associations.take());
+        self.exclude = other.exclude.or(self.exclude.take());
+        self.include_paths = other.include_paths.or(self.include_paths.take());
+        self.max_file_size = other.max_file_size.or(self.max_file_size);
+        self.threads = other.threads.or(self.threads);
+        self.database = other.database.or(self.database.take());
+        self.watch = other.watch.or(self.watch);
+        self.encoding = other.encoding.or(self.encoding.take());
+        self.encoding_overrides = other.encoding_overrides.or(self.encoding_overrides.take());
+    }
+
+    /// Make relative paths absolute.
+    ///
+    /// # Arguments
+    ///
+    /// + `base` - The directory paths are relative to.
+    fn resolve_paths(&mut self, base: &std::path::Path) {
+        if let Some(paths) = &mut self.include_paths {
+            for path in paths.iter_mut() {
+                *path = base.join(&path);
+            }
+        }
+        if let Some(path) = &mut self.database {
+            *path = base.join(&path);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SettingsInner {
+    /// Layers by source.
+    layers: std::collections::BTreeMap<Source, Layer>,
+
+    /// All layers merged.
+    merged: Layer,
+
+    /// Compiled `exclude` patterns.
+    exclude: globset::GlobSet,
+}
+
+/// Settings merged from all layers, shared by all threads.
+#[derive(Debug, Default, Clone)]
+pub struct Settings {
+    inner: std::sync::Arc<std::sync::RwLock<SettingsInner>>,
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings::default()
+    }
+
+    /// Replace the layer of a source.
+    ///
+    /// # Arguments
+    ///
+    /// + `source` - Where the layer comes from.
+    /// + `layer` - The layer.
+    ///
+    /// # Returns
+    ///
+    /// + An error if the layer is invalid, it is not applied then.
+    pub fn set(&self, source: Source, layer: Layer) -> crate::Result<()> {
+        let mut inner = self.inner.write().unwrap();
+        let mut layers = inner.layers.clone();
+        layers.insert(source, layer);
+
+        let mut merged = Layer::default();
+        for layer in layers.values() {
+            merged.merge(layer.clone());
+        }
+        let exclude = compile_globs(merged.exclude.as_deref().unwrap_or_default())?;
+
+        inner.layers = layers;
+        inner.merged = merged;
+        inner.exclude = exclude;
+        Ok(())
+    }
+
+    /// Get the settings merged from all layers.
+    pub fn get(&self) -> Layer {
+        self.inner.read().unwrap().merged.clone()
+    }
+
+    /// Check whether a file is indexed, by `exclude` and `maxFileSize`.
+    ///
+    /// # Arguments
+    ///
+    /// + `file` - The file.
+    pub fn is_indexed(&self, file: &crate::db::FileInfo) -> bool {
+        let inner = self.inner.read().unwrap();
+        if inner.exclude.is_match(&file.path) {
+            return false;
+        }
+        match inner.merged.max_file_size {
+            Some(v) => file.size as u64 <= v,
+            None => true,
+        }
+    }
+}
+
+/// Get the path of the user configuration file.
+///
+/// # Returns
+///
+/// + `syntaxforest/config.toml` in the user configuration directory.
+pub fn user_file() -> Option<std::path::PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(v) if !v.is_empty() => std::path::PathBuf::from(v),
+        _ if cfg!(windows) => std::path::PathBuf::from(std::env::var_os("APPDATA")?),
+        _ => std::path::PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("syntaxforest").join("config.toml"))
+}
+
+/// Compile glob patterns, relative patterns match anywhere in the path.
+///
+/// # Arguments
+///
+/// + `patterns` - The patterns.
+///
+/// # Returns
+///
+/// + The compiled patterns.
+pub fn compile_globs<S: AsRef<str>>(patterns: &[S]) -> crate::Result<globset::GlobSet> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        let glob = globset::Glob::new(&glob_pattern(pattern))
+            .map_err(|e| crate::Error::Config(format!("invalid pattern {}: {}", pattern, e)))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| crate::Error::Config(e.to_string()))
+}
+
+/// Make a pattern match anywhere in the path if it is relative.
+///
+/// # Arguments
+///
+/// + `pattern` - The pattern, e.g. `*.c`.
+///
+/// # Returns
+///
+/// + The glob matched against full paths, e.g. `**/*.c`.
+pub fn glob_pattern(pattern: &str) -> String {
+    match pattern.starts_with('/') || pattern.starts_with("**") {
+        true => pattern.to_string(),
+        false => format!("**/{}", pattern),
+    }
+}
@@ -1,3 +1,60 @@
+use crate::db::{TagInfo, TagKind, TagRange, XrefInfo, XrefKind};
+use crate::syntax::{LocalDeclaration, SymbolAt};
+
+/// TreeSitter node kind.
+#[derive(Debug, Copy, Clone)]
+enum TreeSitterNodeKind {
+    FunctionDefinition,
+    Declaration,
+    TypeDefinition,
+    StructSpecifier,
+    UnionSpecifier,
+    EnumSpecifier,
+    Enumerator,
+    FieldDeclaration,
+    PreprocDef,
+    PreprocFunctionDef,
+    PreprocCall,
+    Identifier,
+    TypeIdentifier,
+    FieldIdentifier,
+}
+
+impl std::convert::TryFrom<&str> for TreeSitterNodeKind {
+    type Error = ();
+
+    /// Convert from node kind name to `TreeSitterNodeKind`.
+    ///
+    /// The numeric kind ID is not stable between grammar versions, so the name is used.
+    ///
+    /// # Arguments
+    ///
+    /// + `value` - Node kind name to convert.
+    ///
+    /// # Returns
+    ///
+    /// + `TreeSitterNodeKind` converted from node kind name.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "function_definition" => Ok(TreeSitterNodeKind::FunctionDefinition),
+            "declaration" => Ok(TreeSitterNodeKind::Declaration),
+            "type_definition" => Ok(TreeSitterNodeKind::TypeDefinition),
+            "struct_specifier" => Ok(TreeSitterNodeKind::StructSpecifier),
+            "union_specifier" => Ok(TreeSitterNodeKind::UnionSpecifier),
+            "enum_specifier" => Ok(TreeSitterNodeKind::EnumSpecifier),
+            "enumerator" => Ok(TreeSitterNodeKind::Enumerator),
+            "field_declaration" => Ok(TreeSitterNodeKind::FieldDeclaration),
+            "preproc_def" => Ok(TreeSitterNodeKind::PreprocDef),
+            "preproc_function_def" => Ok(TreeSitterNodeKind::PreprocFunctionDef),
+            "preproc_call" => Ok(TreeSitterNodeKind::PreprocCall),
+            "identifier" => Ok(TreeSitterNodeKind::Identifier),
+            "type_identifier" => Ok(TreeSitterNodeKind::TypeIdentifier),
+            "field_identifier" => Ok(TreeSitterNodeKind::FieldIdentifier),
+            _ => Err(()),
+        }
+    }
+}
+
 pub struct SyntaxTreeC {}
 
 impl SyntaxTreeC {
@@ -7,63 +64,833 @@ impl SyntaxTreeC {
 }
 
 impl crate::syntax::SyntaxTree for SyntaxTreeC {
-    fn parser(&self, source: &String, db: &crate::db::SqliteClient) -> crate::Result<()> {
-        let mut parser = tree_sitter::Parser::new();
-        parser.set_language(&tree_sitter_c::language()).unwrap();
+    fn parser(
+        &self,
+        path: &std::path::Path,
+        source: &str,
+    ) -> crate::Result<(tree_sitter::Tree, crate::db::FileIndex)> {
+        let tree = parse(source, None)?;
+        let mut collector = TagCollector::new(source, path);
+        parser_ast(&mut collector, &mut tree.walk())?;
+
+        let index = crate::db::FileIndex {
+            path: path.to_path_buf(),
+            tags: collector.tags,
+            xrefs: collector.xrefs,
+            hash: None,
+        };
+        Ok((tree, index))
+    }
+
+    fn parser_incremental(
+        &self,
+        path: &std::path::Path,
+        source: &str,
+        old: &tree_sitter::Tree,
+        edit: &tree_sitter::InputEdit,
+        db: &crate::db::SqliteClient,
+    ) -> crate::Result<tree_sitter::Tree> {
+        let tree = parse(source, Some(old))?;
+
+        // Rows touched by the edit or by syntax changes, after the change.
+        let mut beg = edit.start_position.row;
+        let mut end = edit.new_end_position.row;
+        for range in old.changed_ranges(&tree) {
+            beg = beg.min(range.start_point.row);
+            end = end.max(range.end_point.row);
+        }
+
+        // Grow to whole items of both trees, so no item is split. Positions in the
+        // edited old tree are already shifted by the edit.
+        loop {
+            let (b, e) = item_rows(&tree, item_rows(old, (beg, end)));
+            if (b, e) == (beg, end) {
+                break;
+            }
+            (beg, end) = (b, e);
+        }
+
+        // Macros defined anywhere in the file are known.
+        let mut collector = TagCollector::new(source, path);
+        for tag in db.find_tags_by_path(path)? {
+            if matches!(tag.kind, TagKind::Macro | TagKind::FunctionMacro) {
+                collector.macros.insert(tag.name);
+            }
+        }
+
+        for (item, field) in items(&tree) {
+            if first_row(&item) > end || last_row(&item) < beg {
+                continue;
+            }
+
+            collector.scopes.clear();
+            let mut cursor = item.walk();
+            pick(&mut collector, &item, 0, field);
+            parser_ast(&mut collector, &mut cursor)?;
+        }
+
+        // Rows after the edit are moved by the edit.
+        let row_delta = edit.new_end_position.row as i64 - edit.old_end_position.row as i64;
+        db.patch_index(
+            path,
+            beg as i64,
+            end as i64 - row_delta,
+            row_delta,
+            &collector.tags,
+            &collector.xrefs,
+        )?;
+
+        Ok(tree)
+    }
+
+    fn symbol_at(&self, source: &str, row: usize, col: usize) -> Option<SymbolAt> {
+        let tree = parse(source, None).ok()?;
+        let point = tree_sitter::Point::new(row, col);
+        let node = tree
+            .root_node()
+            .named_descendant_for_point_range(point, point)?;
+
+        // The path of include directive.
+        let include = match node.kind() {
+            "preproc_include" => node.child_by_field_name("path"),
+            _ => std::iter::successors(Some(node), |v| v.parent())
+                .take(3)
+                .find(|v| v.parent().is_some_and(|p| p.kind() == "preproc_include")),
+        };
+        if let Some(include) = include {
+            let (path, system) = include_path(source, &include)?;
+            return Some(SymbolAt::Include {
+                path,
+                system,
+                range: include.range().into(),
+            });
+        }
+
+        match node.kind() {
+            "identifier" | "type_identifier" | "field_identifier" => Some(SymbolAt::Identifier {
+                name: node.utf8_text(source.as_bytes()).ok()?.to_string(),
+                range: node.range().into(),
+                local: match node.kind() {
+                    "identifier" => local_declaration(source, &node),
+                    _ => None,
+                },
+            }),
+
+            // Macro body is not parsed, pick the word under cursor.
+            "preproc_arg" => word_at(source, row, col),
+
+            _ => None,
+        }
+    }
+
+    fn includes(&self, source: &str) -> Vec<(String, bool)> {
+        let mut ret = Vec::new();
+        let tree = match parse(source, None) {
+            Ok(v) => v,
+            Err(_) => return ret,
+        };
+        let query = match tree_sitter::Query::new(
+            &tree_sitter_c::language(),
+            "(preproc_include path: (_) @path)",
+        ) {
+            Ok(v) => v,
+            Err(_) => return ret,
+        };
+
+        let mut cursor = tree_sitter::QueryCursor::new();
+        for m in cursor.matches(&query, tree.root_node(), source.as_bytes()) {
+            for capture in m.captures {
+                if let Some(v) = include_path(source, &capture.node) {
+                    ret.push(v);
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// Find the local variable or parameter an identifier names.
+///
+/// Blocks enclosing the identifier are searched from inside out for a declaration of the
+/// name before it, up to the parameters of the function.
+///
+/// # Arguments
+///
+/// + `source` - The source code.
+/// + `node` - The identifier node.
+///
+/// # Returns
+///
+/// + The declaration, or `None` if the name is not declared in the function.
+fn local_declaration(source: &str, node: &tree_sitter::Node) -> Option<LocalDeclaration> {
+    let name = node.utf8_text(source.as_bytes()).ok()?;
+    let declares = |declarator: tree_sitter::Node| {
+        resolve_declarator(declarator).and_then(|(v, params)| {
+            let found = params.is_none()
+                && v.start_byte() <= node.start_byte()
+                && v.utf8_text(source.as_bytes()).ok() == Some(name);
+            found.then(|| TagRange::from(v.range()))
+        })
+    };
+
+    for scope in std::iter::successors(node.parent(), |v| v.parent()) {
+        let mut walker = scope.walk();
+        let found = match scope.kind() {
+            "compound_statement" | "for_statement" => scope
+                .named_children(&mut walker)
+                .filter(|v| v.kind() == "declaration")
+                .flat_map(|v| {
+                    let mut walker = v.walk();
+                    v.children_by_field_name("declarator", &mut walker)
+                        .collect::<Vec<_>>()
+                })
+                .find_map(declares),
+            "function_definition" => scope
+                .child_by_field_name("declarator")
+                .and_then(resolve_declarator)
+                .and_then(|(_, params)| params)
+                .and_then(|params| {
+                    params
+                        .named_children(&mut walker)
+                        .filter(|v| v.kind() == "parameter_declaration")
+                        .filter_map(|v| v.child_by_field_name("declarator"))
+                        .find_map(declares)
+                }),
+            _ => None,
+        };
 
-        let tree = parser.parse(source, None).unwrap();
-        let mut cursor = tree.walk();
+        if let Some(found) = found {
+            return Some(LocalDeclaration {
+                range: found,
+                scope: scope.range().into(),
+            });
+        }
+        if scope.kind() == "function_definition" {
+            break;
+        }
+    }
+    None
+}
+
+/// Parse C source.
+///
+/// # Arguments
+///
+/// + `source` - The source code.
+/// + `old` - The edited old tree, for incremental parsing.
+///
+/// # Returns
+///
+/// + The syntax tree.
+fn parse(source: &str, old: Option<&tree_sitter::Tree>) -> crate::Result<tree_sitter::Tree> {
+    thread_local! {
+        // Each thread keeps its own parser, so files are parsed in parallel.
+        static PARSER: std::cell::RefCell<Option<tree_sitter::Parser>> = {
+            let mut parser = tree_sitter::Parser::new();
+            std::cell::RefCell::new(match parser.set_language(&tree_sitter_c::language()) {
+                Ok(_) => Some(parser),
+                Err(e) => {
+                    tracing::error!("load C grammar failed: {}", e);
+                    None
+                }
+            })
+        };
+    }
+
+    PARSER.with(|parser| {
+        let mut parser = parser.borrow_mut();
+        let parser = parser
+            .as_mut()
+            .ok_or_else(|| crate::Error::Parse(String::from("C grammar not loaded")))?;
+        parser
+            .parse(source, old)
+            .ok_or_else(|| crate::Error::Parse(String::from("C parser aborted")))
+    })
+}
+
+/// Get independent items of file scope, such as functions and declarations.
+///
+/// Conditional preprocessor blocks at file scope are expanded into their children.
+///
+/// # Arguments
+///
+/// + `tree` - The syntax tree.
+///
+/// # Returns
+///
+/// + List of items, and their field names in parent.
+fn items(tree: &tree_sitter::Tree) -> Vec<(tree_sitter::Node<'_>, Option<&'static str>)> {
+    let mut ret = Vec::new();
+    let mut cursor = tree.walk();
+    let mut pending = vec![tree.root_node()];
+
+    while let Some(node) = pending.pop() {
+        cursor.reset(node);
+        if !cursor.goto_first_child() {
+            continue;
+        }
+        loop {
+            let child = cursor.node();
+            match child.kind() {
+                "preproc_if" | "preproc_ifdef" | "preproc_else" | "preproc_elif"
+                | "preproc_elifdef" => pending.push(child),
+                _ => ret.push((child, cursor.field_name())),
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+
+    ret
+}
+
+/// Grow a row range to cover every item it touches.
+///
+/// # Arguments
+///
+/// + `tree` - The syntax tree.
+/// + `rows` - The first and last row.
+///
+/// # Returns
+///
+/// + The grown range.
+fn item_rows(tree: &tree_sitter::Tree, rows: (usize, usize)) -> (usize, usize) {
+    let (mut beg, mut end) = rows;
+    for (item, _) in items(tree) {
+        let (b, e) = (first_row(&item), last_row(&item));
+        if b <= end && e >= beg {
+            beg = beg.min(b);
+            end = end.max(e);
+        }
+    }
+    (beg, end)
+}
 
-        parser_ast(source, &mut cursor, db).unwrap();
+/// The first row of a node.
+fn first_row(node: &tree_sitter::Node) -> usize {
+    node.start_position().row
+}
+
+/// The last row that contains text of a node, a trailing newline does not count.
+fn last_row(node: &tree_sitter::Node) -> usize {
+    let (beg, end) = (node.start_position(), node.end_position());
+    match end.column == 0 && end.row > beg.row {
+        true => end.row - 1,
+        false => end.row,
+    }
+}
 
-        Ok(())
+/// Get the path of an include directive.
+///
+/// # Arguments
+///
+/// + `source` - The source code.
+/// + `node` - The `path` node of `preproc_include`.
+///
+/// # Returns
+///
+/// + The included path, and whether it is a `<system>` include.
+fn include_path(source: &str, node: &tree_sitter::Node) -> Option<(String, bool)> {
+    let text = node.utf8_text(source.as_bytes()).ok()?;
+    match node.kind() {
+        "string_literal" => Some((text.trim_matches('"').to_string(), false)),
+        "system_lib_string" => Some((
+            text.trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string(),
+            true,
+        )),
+        _ => None,
+    }
+}
+
+/// Get the identifier-like word at position.
+///
+/// # Arguments
+///
+/// + `source` - The source code.
+/// + `row` - Zero based row.
+/// + `col` - Zero based column in bytes.
+///
+/// # Returns
+///
+/// + The word at position.
+fn word_at(source: &str, row: usize, col: usize) -> Option<SymbolAt> {
+    let line = source.lines().nth(row)?.as_bytes();
+    let is_word = |c: &u8| c.is_ascii_alphanumeric() || *c == b'_';
+
+    let beg = line[..col.min(line.len())]
+        .iter()
+        .rposition(|c| !is_word(c))
+        .map_or(0, |v| v + 1);
+    let end = line[beg..]
+        .iter()
+        .position(|c| !is_word(c))
+        .map_or(line.len(), |v| beg + v);
+    if beg == end || line[beg].is_ascii_digit() {
+        return None;
+    }
+
+    Some(SymbolAt::Identifier {
+        name: String::from_utf8_lossy(&line[beg..end]).to_string(),
+        range: TagRange {
+            beg_row: row as i64,
+            beg_col: beg as i64,
+            end_row: row as i64,
+            end_col: end as i64,
+        },
+        local: None,
+    })
+}
+
+/// Tags and xrefs collected from one syntax tree.
+struct TagCollector<'a> {
+    /// The source code.
+    source: &'a [u8],
+
+    /// The path of the source file.
+    path: &'a std::path::Path,
+
+    /// Tags found so far.
+    tags: Vec<TagInfo>,
+
+    /// Xrefs found so far.
+    xrefs: Vec<XrefInfo>,
+
+    /// Names of macros defined so far.
+    macros: std::collections::HashSet<String>,
+
+    /// Enclosing tags, as pairs of node depth and index in `tags`.
+    scopes: Vec<(u32, usize)>,
+}
+
+impl<'a> TagCollector<'a> {
+    /// Create an empty collector.
+    ///
+    /// # Arguments
+    ///
+    /// + `source` - The source code.
+    /// + `path` - The path of the source file.
+    fn new(source: &'a str, path: &'a std::path::Path) -> Self {
+        TagCollector {
+            source: source.as_bytes(),
+            path,
+            tags: Vec::new(),
+            xrefs: Vec::new(),
+            macros: std::collections::HashSet::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Get text of the node.
+    fn text(&self, node: &tree_sitter::Node) -> String {
+        String::from_utf8_lossy(&self.source[node.byte_range()]).to_string()
+    }
+
+    /// Check if we are inside a function body.
+    fn in_function(&self) -> bool {
+        self.scopes
+            .iter()
+            .any(|(_, idx)| self.tags[*idx].kind == TagKind::Function)
+    }
+
+    /// Add a tag.
+    ///
+    /// # Arguments
+    ///
+    /// + `kind` - The kind of tag.
+    /// + `node` - The node of the whole definition.
+    /// + `name` - The node of the name.
+    ///
+    /// # Returns
+    ///
+    /// + The index of the new tag.
+    fn push(&mut self, kind: TagKind, node: &tree_sitter::Node, name: &tree_sitter::Node) -> usize {
+        let name_range = name.range().into();
+        let name = self.text(name);
+        self.push_range(kind, node, name_range, name)
+    }
+
+    /// Add a tag whose name is not a node.
+    ///
+    /// # Arguments
+    ///
+    /// + `kind` - The kind of tag.
+    /// + `node` - The node of the whole definition.
+    /// + `name_range` - The range of the name.
+    /// + `name` - The name.
+    ///
+    /// # Returns
+    ///
+    /// + The index of the new tag.
+    fn push_range(
+        &mut self,
+        kind: TagKind,
+        node: &tree_sitter::Node,
+        name_range: TagRange,
+        name: String,
+    ) -> usize {
+        let scope = match kind {
+            TagKind::Macro | TagKind::FunctionMacro => {
+                self.macros.insert(name.clone());
+                None
+            }
+            // The typedef is a sibling of the type it names, not its parent.
+            TagKind::Struct | TagKind::Union | TagKind::Enum => self
+                .scopes
+                .iter()
+                .rev()
+                .find(|(_, idx)| self.tags[*idx].kind != TagKind::Typedef)
+                .map(|(_, idx)| *idx as i64),
+            _ => self.scopes.last().map(|(_, idx)| *idx as i64),
+        };
+
+        self.tags.push(TagInfo {
+            id: 0,
+            kind,
+            range: node.range().into(),
+            name_range,
+            path: self.path.to_path_buf(),
+            name,
+            scope,
+            detail: None,
+        });
+
+        self.tags.len() - 1
+    }
+
+    /// Use text of the node as detail of a tag, whitespace is collapsed.
+    ///
+    /// # Arguments
+    ///
+    /// + `idx` - The index of the tag.
+    /// + `node` - The node of detail, e.g. the parameter list.
+    fn set_detail(&mut self, idx: usize, node: &tree_sitter::Node) {
+        let text = self.text(node);
+        let detail = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        self.tags[idx].detail = Some(detail);
+    }
+
+    /// Add a xref held by the innermost enclosing tag.
+    ///
+    /// # Arguments
+    ///
+    /// + `kind` - The kind of xref.
+    /// + `node` - The node of the identifier.
+    fn push_xref(&mut self, kind: XrefKind, node: &tree_sitter::Node) {
+        let name = self.text(node);
+
+        // Macros defined in this file are known by name.
+        let kind = match self.macros.contains(&name) {
+            true => XrefKind::Macro,
+            false => kind,
+        };
+
+        self.xrefs.push(XrefInfo {
+            id: 0,
+            kind,
+            range: node.range().into(),
+            path: self.path.to_path_buf(),
+            name,
+            hold: self.scopes.last().map(|(_, idx)| *idx as i64),
+        });
     }
 }
 
 fn parser_ast(
-    source: &String,
+    collector: &mut TagCollector,
     cursor: &mut tree_sitter::TreeCursor,
-    _db: &crate::db::SqliteClient,
 ) -> crate::Result<()> {
     let mut recurse = true;
     let mut finished = false;
 
     while !finished {
-        if recurse && cursor.goto_first_child() {
+        if (recurse && cursor.goto_first_child()) || cursor.goto_next_sibling() {
             recurse = true;
 
-            pick_node(&source, cursor);
+            pick_node(collector, cursor);
+        } else if cursor.goto_parent() {
+            recurse = false;
         } else {
-            if cursor.goto_next_sibling() {
-                recurse = true;
-
-                pick_node(&source, cursor);
-            } else if cursor.goto_parent() {
-                recurse = false;
-            } else {
-                finished = true;
-            }
+            finished = true;
         }
     }
 
     Ok(())
 }
 
-fn pick_node(source: &String, cursor: &mut tree_sitter::TreeCursor) {
+fn pick_node(collector: &mut TagCollector, cursor: &mut tree_sitter::TreeCursor) {
     let node = cursor.node();
+    pick(collector, &node, cursor.depth(), cursor.field_name());
+}
+
+/// Record tag or xref of a node.
+///
+/// # Arguments
+///
+/// + `collector` - The tag collector.
+/// + `node` - The node.
+/// + `depth` - The depth of the node, used to track enclosing tags.
+/// + `field` - The field name of the node in its parent.
+fn pick(collector: &mut TagCollector, node: &tree_sitter::Node, depth: u32, field: Option<&str>) {
+    let node = *node;
     tracing::trace!(
-        "{}",
-        format!(
-            "{}`{}`({}): {}",
-            "  ".repeat(cursor.depth() as usize),
-            node.kind(),
-            node.kind_id(),
-            node.utf8_text(source.as_bytes()).unwrap()
-        )
+        "{}`{}`({}): {}",
+        "  ".repeat(depth as usize),
+        node.kind(),
+        node.kind_id(),
+        collector.text(&node)
     );
 
-    match node.kind_id().try_into() {
-        Ok(crate::method::TreeSitterNodeKind::PreprocFunctionDef) => {}
-        Err(_) => {}
+    // Leave scopes that do not contain this node.
+    while let Some((d, _)) = collector.scopes.last() {
+        if *d < depth {
+            break;
+        }
+        collector.scopes.pop();
+    }
+
+    let kind = match TreeSitterNodeKind::try_from(node.kind()) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+
+    match kind {
+        TreeSitterNodeKind::FunctionDefinition => {
+            if let Some((name, params)) = node
+                .child_by_field_name("declarator")
+                .and_then(resolve_declarator)
+            {
+                let idx = collector.push(TagKind::Function, &node, &name);
+                if let Some(params) = params {
+                    collector.set_detail(idx, &params);
+                }
+                collector.scopes.push((depth, idx));
+            }
+        }
+
+        TreeSitterNodeKind::Declaration => {
+            // Local variables are not tags.
+            if collector.in_function() {
+                return;
+            }
+
+            let mut walker = node.walk();
+            for declarator in node.children_by_field_name("declarator", &mut walker) {
+                match resolve_declarator(declarator) {
+                    Some((name, Some(params))) => {
+                        let idx = collector.push(TagKind::Prototype, &node, &name);
+                        collector.set_detail(idx, &params);
+                    }
+                    Some((name, None)) => {
+                        collector.push(TagKind::Variable, &node, &name);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        TreeSitterNodeKind::TypeDefinition => {
+            let mut walker = node.walk();
+            let mut last = None;
+            for declarator in node.children_by_field_name("declarator", &mut walker) {
+                if let Some((name, _)) = resolve_declarator(declarator) {
+                    last = Some(collector.push(TagKind::Typedef, &node, &name));
+                }
+            }
+
+            // Fields of anonymous struct belong to the typedef.
+            if let Some(idx) = last {
+                collector.scopes.push((depth, idx));
+            }
+        }
+
+        TreeSitterNodeKind::StructSpecifier
+        | TreeSitterNodeKind::UnionSpecifier
+        | TreeSitterNodeKind::EnumSpecifier => {
+            // Only the one with body is a definition.
+            if node.child_by_field_name("body").is_none() {
+                return;
+            }
+            let name = match node.child_by_field_name("name") {
+                Some(v) => v,
+                None => return,
+            };
+
+            let tag_kind = match kind {
+                TreeSitterNodeKind::StructSpecifier => TagKind::Struct,
+                TreeSitterNodeKind::UnionSpecifier => TagKind::Union,
+                _ => TagKind::Enum,
+            };
+            let idx = collector.push(tag_kind, &node, &name);
+            collector.scopes.push((depth, idx));
+        }
+
+        TreeSitterNodeKind::Enumerator => {
+            if let Some(name) = node.child_by_field_name("name") {
+                collector.push(TagKind::Enumerator, &node, &name);
+            }
+        }
+
+        TreeSitterNodeKind::FieldDeclaration => {
+            let mut walker = node.walk();
+            for declarator in node.children_by_field_name("declarator", &mut walker) {
+                if let Some((name, _)) = resolve_declarator(declarator) {
+                    collector.push(TagKind::Field, &node, &name);
+                }
+            }
+        }
+
+        TreeSitterNodeKind::PreprocDef => {
+            if let Some(name) = node.child_by_field_name("name") {
+                collector.push(TagKind::Macro, &node, &name);
+            }
+        }
+
+        TreeSitterNodeKind::PreprocFunctionDef => {
+            if let Some(name) = node.child_by_field_name("name") {
+                let idx = collector.push(TagKind::FunctionMacro, &node, &name);
+                if let Some(params) = node.child_by_field_name("parameters") {
+                    collector.set_detail(idx, &params);
+                }
+            }
+        }
+
+        TreeSitterNodeKind::PreprocCall => pick_misplaced_define(collector, &node),
+
+        TreeSitterNodeKind::Identifier
+        | TreeSitterNodeKind::TypeIdentifier
+        | TreeSitterNodeKind::FieldIdentifier => {
+            if let Some(kind) = classify_reference(&node, field) {
+                collector.push_xref(kind, &node);
+            }
+        }
+    }
+}
+
+/// Decide whether an identifier is a reference, and which kind it is.
+///
+/// # Arguments
+///
+/// + `node` - The identifier node.
+/// + `field` - The field name of the identifier in its parent.
+///
+/// # Returns
+///
+/// + The kind of reference, or `None` if the identifier is not a reference.
+fn classify_reference(node: &tree_sitter::Node, field: Option<&str>) -> Option<XrefKind> {
+    let parent = node.parent()?;
+
+    // Declared names are definitions, not references.
+    if field == Some("declarator") || parent.kind() == "preproc_params" {
+        return None;
+    }
+
+    match node.kind() {
+        "type_identifier" => match (parent.kind(), field) {
+            ("struct_specifier" | "union_specifier" | "enum_specifier", Some("name"))
+                if parent.child_by_field_name("body").is_some() =>
+            {
+                None
+            }
+            _ => Some(XrefKind::Type),
+        },
+
+        "field_identifier" => Some(XrefKind::Field),
+
+        _ => match (parent.kind(), field) {
+            ("macro_type_specifier" | "preproc_ifdef", Some("name")) => Some(XrefKind::Macro),
+            ("preproc_defined", _) => Some(XrefKind::Macro),
+            (_, Some("name")) => None,
+            ("call_expression", Some("function")) => Some(XrefKind::Call),
+            ("assignment_expression", Some("left")) => Some(XrefKind::Write),
+            ("update_expression", Some("argument")) => Some(XrefKind::Write),
+            _ => Some(XrefKind::Read),
+        },
+    }
+}
+
+/// Pick `#define` that the grammar does not expect, e.g. inside an enumerator list,
+/// which is parsed as a `preproc_call`.
+///
+/// # Arguments
+///
+/// + `collector` - The tag collector.
+/// + `node` - The `preproc_call` node.
+fn pick_misplaced_define(collector: &mut TagCollector, node: &tree_sitter::Node) {
+    let directive = match node.child_by_field_name("directive") {
+        Some(v) => collector.text(&v),
+        None => return,
+    };
+    if directive.trim_start_matches('#').trim() != "define" {
+        return;
+    }
+
+    let arg = match node.child_by_field_name("argument") {
+        Some(v) => v,
+        None => return,
+    };
+    let text = collector.text(&arg);
+    let len = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(text.len());
+    if len == 0 {
+        return;
+    }
+
+    let kind = match text[len..].starts_with('(') {
+        true => TagKind::FunctionMacro,
+        false => TagKind::Macro,
+    };
+    let start = arg.start_position();
+    let name_range = TagRange {
+        beg_row: start.row as i64,
+        beg_col: start.column as i64,
+        end_row: start.row as i64,
+        end_col: (start.column + len) as i64,
+    };
+    let idx = collector.push_range(kind, node, name_range, text[..len].to_string());
+
+    if kind == TagKind::FunctionMacro {
+        if let Some(end) = text.find(')') {
+            let detail = text[len..=end].split_whitespace().collect::<Vec<_>>();
+            collector.tags[idx].detail = Some(detail.join(" "));
+        }
+    }
+}
+
+/// Find the declared name in a declarator.
+///
+/// # Arguments
+///
+/// + `node` - The declarator node.
+///
+/// # Returns
+///
+/// + The name node, and the parameter list if the declarator declares a function.
+fn resolve_declarator(
+    node: tree_sitter::Node,
+) -> Option<(tree_sitter::Node, Option<tree_sitter::Node>)> {
+    let mut node = node;
+    let mut params = None;
+
+    loop {
+        match node.kind() {
+            "identifier" | "type_identifier" | "field_identifier" | "primitive_type" => {
+                return Some((node, params));
+            }
+            "function_declarator" => {
+                params = node.child_by_field_name("parameters");
+                node = node.child_by_field_name("declarator")?;
+            }
+            "parenthesized_declarator" | "attributed_declarator" => {
+                node = node.named_child(0)?;
+            }
+            _ => {
+                params = None;
+                node = node.child_by_field_name("declarator")?;
+            }
+        }
     }
 }
@@ -0,0 +1,203 @@
+//! Detect the language of a file from its content.
+
+/// Known languages and their aliases, the first one is the name of the language.
+///
+/// Aliases include LSP language identifiers, Vim filetypes and Emacs modes.
+const LANGUAGES: &[&[&str]] = &[&["C", "c"], &["C++", "cpp", "c++", "cxx", "cc"]];
+
+/// Interpreters that run C source as scripts, and their languages.
+const INTERPRETERS: &[(&str, &str)] = &[("tcc", "C"), ("c", "C"), ("cling", "C++")];
+
+/// Vim only looks for modelines in this many lines at the start and the end of a file.
+const VIM_MODELINES: usize = 5;
+
+/// Find the name of a language by one of its aliases, ignoring case.
+///
+/// # Arguments
+///
+/// + `alias` - The alias, e.g. `c`, `cpp` or `C++`.
+///
+/// # Returns
+///
+/// + The name of the language, e.g. `C`.
+pub fn language_name(alias: &str) -> Option<&'static str> {
+    LANGUAGES
+        .iter()
+        .find(|v| v.iter().any(|a| a.eq_ignore_ascii_case(alias)))
+        .map(|v| v[0])
+}
+
+/// Find the language set by an Emacs or Vim modeline.
+///
+/// Emacs reads `-*- mode: c -*-` or `-*- c -*-` on the first line, or the second one
+/// after a shebang. Vim reads `vim: set ft=c:` or `vim: filetype=c` in the first and
+/// last lines.
+///
+/// # Arguments
+///
+/// + `source` - The content of the file.
+///
+/// # Returns
+///
+/// + The name of the language.
+pub fn modeline(source: &str) -> Option<&'static str> {
+    let lines: Vec<_> = source.lines().collect();
+
+    let emacs = match lines.first() {
+        Some(v) if v.starts_with("#!") => lines.get(1),
+        v => v,
+    };
+    if let Some(lang) = emacs.and_then(|v| emacs_mode(v)) {
+        return Some(lang);
+    }
+
+    let tail = lines.len().saturating_sub(VIM_MODELINES).max(VIM_MODELINES);
+    lines
+        .iter()
+        .take(VIM_MODELINES)
+        .chain(lines.iter().skip(tail))
+        .find_map(|v| vim_filetype(v))
+}
+
+/// Find the language run by the interpreter of a shebang, e.g. `#!/usr/bin/tcc -run`.
+///
+/// # Arguments
+///
+/// + `source` - The content of the file.
+///
+/// # Returns
+///
+/// + The name of the language.
+pub fn shebang(source: &str) -> Option<&'static str> {
+    let line = source.lines().next()?.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+
+    // `#!/usr/bin/env -S tcc -run` runs the first word that is not an option.
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|v| !v.starts_with('-'))?;
+    }
+
+    INTERPRETERS
+        .iter()
+        .find(|(name, _)| *name == program)
+        .map(|(_, lang)| *lang)
+}
+
+/// Guess whether a header is written in C++ rather than C.
+///
+/// Declarations only C++ has are looked for, outside of comments. A C header guarded by
+/// `#ifdef __cplusplus` for `extern "C"` is still C.
+///
+/// # Arguments
+///
+/// + `source` - The content of the header.
+pub fn is_cpp_header(source: &str) -> bool {
+    const KEYWORDS: &[&str] = &["class", "namespace", "template", "using", "typename"];
+    const ACCESS: &[&str] = &["public:", "private:", "protected:"];
+
+    let mut in_comment = false;
+    for line in source.lines() {
+        let mut line = line.trim();
+
+        // Skip block comments, they may span lines.
+        if in_comment {
+            match line.find("*/") {
+                Some(i) => {
+                    line = line[i + 2..].trim_start();
+                    in_comment = false;
+                }
+                None => continue,
+            }
+        }
+        if let Some(i) = line.find("/*") {
+            in_comment = !line[i..].contains("*/");
+            line = &line[..i];
+        }
+        if let Some(i) = line.find("//") {
+            line = &line[..i];
+        }
+
+        // Standard C++ headers have no suffix, e.g. `#include <vector>`.
+        if let Some(header) = line
+            .strip_prefix('#')
+            .map(|v| v.trim_start())
+            .and_then(|v| v.strip_prefix("include"))
+            .and_then(|v| v.trim().strip_prefix('<'))
+            .and_then(|v| v.strip_suffix('>'))
+        {
+            if !header.contains('.') {
+                return true;
+            }
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let first = line
+            .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .next();
+        if first.is_some_and(|v| KEYWORDS.contains(&v))
+            || ACCESS.iter().any(|v| line.starts_with(v))
+            || line.contains("::")
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Find the mode of an Emacs file variable line.
+///
+/// # Arguments
+///
+/// + `line` - The line.
+fn emacs_mode(line: &str) -> Option<&'static str> {
+    let start = line.find("-*-")? + 3;
+    let end = start + line[start..].find("-*-")?;
+    let vars = line[start..end].trim();
+
+    // A single word is the mode, otherwise variables are separated by `;`.
+    if !vars.contains(':') {
+        return language_name(vars);
+    }
+    vars.split(';').find_map(|v| {
+        let (name, value) = v.split_once(':')?;
+        match name.trim().eq_ignore_ascii_case("mode") {
+            true => language_name(value.trim()),
+            false => None,
+        }
+    })
+}
+
+/// Find the filetype of a Vim modeline.
+///
+/// # Arguments
+///
+/// + `line` - The line.
+fn vim_filetype(line: &str) -> Option<&'static str> {
+    // The marker starts a word, e.g. `/* vim: set ft=c: */` or `// vim:ft=c`.
+    let start = line
+        .match_indices(|c: char| c.is_whitespace())
+        .map(|(i, c)| i + c.len())
+        .chain([0])
+        .filter_map(|i| {
+            ["vim:", "vi:", "ex:"]
+                .iter()
+                .find(|v| line[i..].starts_with(*v))
+                .map(|v| i + v.len())
+        })
+        .min()?;
+
+    // Options are separated by spaces or `:`, `set` is optional.
+    line[start..]
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|v| {
+            let (name, value) = v.split_once('=')?;
+            match name {
+                "ft" | "filetype" => language_name(value),
+                _ => None,
+            }
+        })
+}
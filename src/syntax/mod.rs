@@ -1,18 +1,136 @@
 mod c;
+mod detect;
+
+/// Bytes read from a file without suffix to detect its language.
+const HEAD_SIZE: u64 = 1024;
+
+/// The symbol found at some position of source.
+#[derive(Debug, Clone)]
+pub enum SymbolAt {
+    /// An identifier.
+    Identifier {
+        /// The name of the identifier.
+        name: String,
+        /// The range of the identifier.
+        range: crate::db::TagRange,
+        /// The local variable or parameter it names, `None` if it is not local.
+        local: Option<LocalDeclaration>,
+    },
+
+    /// The path of an include directive.
+    Include {
+        /// The path as written in source.
+        path: String,
+        /// Whether it is a `<system>` include.
+        system: bool,
+        /// The range of the path.
+        range: crate::db::TagRange,
+    },
+}
+
+/// The declaration of a local variable or parameter.
+#[derive(Debug, Clone)]
+pub struct LocalDeclaration {
+    /// The range of the declared name.
+    pub range: crate::db::TagRange,
+    /// The range of the block the name is visible in.
+    pub scope: crate::db::TagRange,
+}
 
 pub trait SyntaxTree {
-    fn parser(&self, source: &String, db: &crate::db::SqliteClient) -> crate::Result<()>;
+    /// Parse the source and collect its tags and xrefs.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the source file.
+    /// + `source` - The content of the source file.
+    ///
+    /// # Returns
+    ///
+    /// + The syntax tree, kept for incremental parsing, and the index of the file.
+    fn parser(
+        &self,
+        path: &std::path::Path,
+        source: &str,
+    ) -> crate::Result<(tree_sitter::Tree, crate::db::FileIndex)>;
+
+    /// Reparse the source after an edit, only records of changed items are updated.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the source file.
+    /// + `source` - The content of the source file after the edit.
+    /// + `old` - The old syntax tree, already edited by `edit`.
+    /// + `edit` - The edit.
+    /// + `db` - The database.
+    ///
+    /// # Returns
+    ///
+    /// + The new syntax tree.
+    fn parser_incremental(
+        &self,
+        path: &std::path::Path,
+        source: &str,
+        old: &tree_sitter::Tree,
+        edit: &tree_sitter::InputEdit,
+        db: &crate::db::SqliteClient,
+    ) -> crate::Result<tree_sitter::Tree>;
+
+    /// Find the symbol at position.
+    ///
+    /// # Arguments
+    ///
+    /// + `source` - The content of the source file.
+    /// + `row` - Zero based row.
+    /// + `col` - Zero based column in bytes.
+    ///
+    /// # Returns
+    ///
+    /// + The symbol at position.
+    fn symbol_at(&self, source: &str, row: usize, col: usize) -> Option<SymbolAt>;
+
+    /// Get all include directives.
+    ///
+    /// # Arguments
+    ///
+    /// + `source` - The content of the source file.
+    ///
+    /// # Returns
+    ///
+    /// + List of included paths, and whether it is a `<system>` include.
+    fn includes(&self, source: &str) -> Vec<(String, bool)>;
+}
+
+/// Files associated with a language.
+#[derive(Debug, Clone)]
+struct Association {
+    /// Glob pattern of the files, relative ones match anywhere in the path.
+    pattern: String,
+
+    /// The name of the language.
+    language: String,
+
+    /// Whether it is builtin, the language of builtin ones may be refined by content.
+    builtin: bool,
 }
 
 #[derive(Debug, Default)]
 struct SyntaxParserInner {
     language_table: std::collections::BTreeMap<String, fn() -> Box<dyn SyntaxTree>>,
-    file_association_table: std::collections::BTreeMap<String, String>,
+
+    /// File associations, the first matched one wins.
+    file_association_table: Vec<Association>,
+
+    /// Compiled patterns of `file_association_table`.
+    file_association_globs: globset::GlobSet,
+
+    /// Languages of documents opened by the client.
+    document_languages: std::collections::HashMap<std::path::PathBuf, String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct SyntaxParser {
-    inner: std::sync::Arc<std::sync::Mutex<SyntaxParserInner>>,
+    inner: std::sync::Arc<std::sync::RwLock<SyntaxParserInner>>,
 }
 
 impl SyntaxParser {
@@ -25,59 +143,388 @@ impl SyntaxParser {
         });
 
         // Register file association.
-        inner
-            .file_association_table
-            .insert(".c".to_string(), "C".to_string());
+        (inner.file_association_table, inner.file_association_globs) =
+            compile_associations(Vec::new()).expect("builtin associations are valid");
 
-        let parser = SyntaxParser {
-            inner: std::sync::Arc::new(std::sync::Mutex::new(inner)),
-        };
-        return parser;
+        SyntaxParser {
+            inner: std::sync::Arc::new(std::sync::RwLock::new(inner)),
+        }
     }
 
-    pub fn filter_file_suffix(
+    /// Associate files with languages, before the builtin ones.
+    ///
+    /// # Arguments
+    ///
+    /// + `associations` - Glob patterns and languages, e.g. `*.inc` and `C`. A pattern
+    ///   like `.inc` matches the suffix.
+    ///
+    /// # Returns
+    ///
+    /// + An error if any pattern is invalid or language is not supported, nothing
+    ///   changes then.
+    pub fn set_associations(
         &self,
-        file_list: &Vec<crate::db::FileInfo>,
-    ) -> Vec<crate::db::FileInfo> {
+        associations: &std::collections::BTreeMap<String, String>,
+    ) -> crate::Result<()> {
+        let mut inner = self.inner.write().unwrap();
+
+        let mut table = Vec::new();
+        for (pattern, lang) in associations {
+            let language = match detect::language_name(lang) {
+                Some(v) if inner.language_table.contains_key(v) => v.to_string(),
+                _ => {
+                    return Err(crate::Error::Config(format!(
+                        "unsupported language {}",
+                        lang
+                    )))
+                }
+            };
+
+            // A bare suffix, e.g. `.h`.
+            let is_suffix = pattern.starts_with('.') && !pattern.contains(['*', '?', '[', '{']);
+            table.push(Association {
+                pattern: match is_suffix {
+                    true => format!("*{}", pattern),
+                    false => pattern.clone(),
+                },
+                language,
+                builtin: false,
+            });
+        }
+
+        (inner.file_association_table, inner.file_association_globs) = compile_associations(table)?;
+        Ok(())
+    }
+
+    /// Set the language of a document opened by the client.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the document.
+    /// + `language_id` - The language ID reported by the client, `None` once the document
+    ///   is closed. Unknown IDs are ignored and the language is detected instead.
+    pub fn set_language_id(&self, path: &std::path::Path, language_id: Option<&str>) {
+        let mut inner = self.inner.write().unwrap();
+        match language_id.and_then(detect::language_name) {
+            Some(v) => inner
+                .document_languages
+                .insert(path.to_path_buf(), v.to_string()),
+            None => inner.document_languages.remove(path),
+        };
+    }
+
+    /// Keep files that may be associated with a language.
+    ///
+    /// # Arguments
+    ///
+    /// + `file_list` - The files.
+    ///
+    /// # Returns
+    ///
+    /// + The files to index.
+    pub fn filter_files(&self, file_list: &[crate::db::FileInfo]) -> Vec<crate::db::FileInfo> {
         let mut ret = Vec::new();
         for file in file_list {
             let path = &file.path;
-            if self.is_match_extension(path) {
+            if self.is_associated(path) {
                 ret.push(file.clone());
             }
         }
-        return ret;
+        ret
     }
 
-    pub fn parser(
+    /// Find the symbol at position.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the source file.
+    /// + `source` - The content of the source file.
+    /// + `row` - Zero based row.
+    /// + `col` - Zero based column in bytes.
+    ///
+    /// # Returns
+    ///
+    /// + The symbol at position, or `None` if nothing found or the file is not supported.
+    pub fn symbol_at(
         &self,
-        path: &std::path::PathBuf,
+        path: &std::path::Path,
+        source: &str,
+        row: usize,
+        col: usize,
+    ) -> Option<SymbolAt> {
+        let lang = self.language(path, source)?;
+        lang.symbol_at(&mask_shebang(source), row, col)
+    }
+
+    /// Get all include directives.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the source file.
+    /// + `source` - The content of the source file.
+    ///
+    /// # Returns
+    ///
+    /// + List of included paths, and whether it is a `<system>` include.
+    pub fn includes(&self, path: &std::path::Path, source: &str) -> Vec<(String, bool)> {
+        match self.language(path, source) {
+            Some(v) => v.includes(&mask_shebang(source)),
+            None => Vec::new(),
+        }
+    }
+
+    /// Read and parse a file on disk.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the source file.
+    /// + `encodings` - Decoding of the file.
+    ///
+    /// # Returns
+    ///
+    /// + The index of the file, empty if its content is not in a supported language.
+    pub fn index_file(
+        &self,
+        path: &std::path::Path,
+        encodings: &crate::encoding::Encodings,
+    ) -> crate::Result<crate::db::FileIndex> {
+        let (content, text) = encodings.read(path)?;
+        let mut index = match self.language(path, &text) {
+            Some(lang) => lang.parser(path, &mask_shebang(&text))?.1,
+            None => crate::db::FileIndex {
+                path: path.to_path_buf(),
+                ..Default::default()
+            },
+        };
+
+        // Hash the raw content, the same as scanning does.
+        index.hash = Some(crate::utils::path::content_hash(&content));
+        Ok(index)
+    }
+
+    /// Parse the content of a file and save the result into database.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the source file.
+    /// + `source` - The content of the source file, may differ from the file on disk.
+    /// + `db` - The database.
+    ///
+    /// # Returns
+    ///
+    /// + The syntax tree, or `None` if the file is not supported. Records of the file are
+    ///   removed then, its language may have changed.
+    pub fn parse_source(
+        &self,
+        path: &std::path::Path,
+        source: &str,
         db: &crate::db::SqliteClient,
-    ) -> crate::Result<()> {
-        let inner = self.inner.lock().unwrap();
+    ) -> crate::Result<Option<tree_sitter::Tree>> {
+        let (tree, index) = match self.language(path, source) {
+            Some(p) => p.parser(path, &mask_shebang(source))?,
+            None => {
+                db.remove_path(path)?;
+                return Ok(None);
+            }
+        };
 
-        for (k, lang) in &inner.file_association_table {
-            let file_path = path.to_str().unwrap();
-            if file_path.ends_with(k.as_str()) {
-                let content = std::fs::read_to_string(path).unwrap();
+        db.update_index(&index)?;
+        Ok(Some(tree))
+    }
 
-                let p = inner.language_table.get(lang).unwrap();
-                p().parser(&content, db).unwrap();
+    /// Reparse the content of a file after an edit.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the source file.
+    /// + `source` - The content of the source file after the edit.
+    /// + `old` - The old syntax tree, already edited by `edit`.
+    /// + `edit` - The edit.
+    /// + `db` - The database.
+    ///
+    /// # Returns
+    ///
+    /// + The new syntax tree, or `None` if the file is not supported. Records of the file
+    ///   are removed then.
+    pub fn parse_incremental(
+        &self,
+        path: &std::path::Path,
+        source: &str,
+        old: &tree_sitter::Tree,
+        edit: &tree_sitter::InputEdit,
+        db: &crate::db::SqliteClient,
+    ) -> crate::Result<Option<tree_sitter::Tree>> {
+        match self.language(path, source) {
+            Some(p) => p
+                .parser_incremental(path, &mask_shebang(source), old, edit, db)
+                .map(Some),
+            None => {
+                db.remove_path(path)?;
+                Ok(None)
             }
         }
+    }
 
-        Ok(())
+    /// Create syntax tree for the file.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the source file.
+    /// + `source` - The content of the source file.
+    ///
+    /// # Returns
+    ///
+    /// + The syntax tree, or `None` if the file is not supported.
+    fn language(&self, path: &std::path::Path, source: &str) -> Option<Box<dyn SyntaxTree>> {
+        let lang = self.detect(path, source)?;
+        let inner = self.inner.read().unwrap();
+        match inner.language_table.get(&lang) {
+            Some(p) => Some(p()),
+            None => {
+                tracing::debug!("{} is {}, which is not supported", path.display(), lang);
+                None
+            }
+        }
     }
 
-    fn is_match_extension(&self, path: &std::path::PathBuf) -> bool {
-        let path = path.to_str().unwrap();
-        let inner = self.inner.lock().unwrap();
+    /// Detect the language of a file.
+    ///
+    /// In order: the language ID of an opened document, a modeline, file associations,
+    /// then a shebang.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the source file.
+    /// + `source` - The content of the source file.
+    ///
+    /// # Returns
+    ///
+    /// + The name of the language, which may have no parser.
+    fn detect(&self, path: &std::path::Path, source: &str) -> Option<String> {
+        let inner = self.inner.read().unwrap();
+        if let Some(v) = inner.document_languages.get(path) {
+            return Some(v.clone());
+        }
+        if let Some(v) = detect::modeline(source) {
+            return Some(v.to_string());
+        }
+
+        if let Some(i) = inner.file_association_globs.matches(path).first() {
+            let association = &inner.file_association_table[*i];
 
-        for (k, _) in &inner.file_association_table {
-            if path.ends_with(k.as_str()) {
-                return true;
+            // `.h` is shared by C and C++.
+            if association.builtin
+                && association.language == "C"
+                && path.extension().is_some_and(|v| v == "h")
+                && detect::is_cpp_header(source)
+            {
+                return Some("C++".to_string());
             }
+            return Some(association.language.clone());
         }
-        return false;
+
+        detect::shebang(source).map(String::from)
+    }
+
+    /// Get glob patterns of all files associated with a language.
+    ///
+    /// # Returns
+    ///
+    /// + List of patterns, e.g. `**/*.c`.
+    pub fn file_patterns(&self) -> Vec<String> {
+        let inner = self.inner.read().unwrap();
+        inner
+            .file_association_table
+            .iter()
+            .map(|v| crate::settings::glob_pattern(&v.pattern))
+            .collect()
+    }
+
+    /// Check whether the file may be associated with a language.
+    ///
+    /// A file without suffix is associated if it starts with a shebang or modeline of a
+    /// supported language.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the file.
+    pub fn is_associated(&self, path: &std::path::Path) -> bool {
+        if self
+            .inner
+            .read()
+            .unwrap()
+            .file_association_globs
+            .is_match(path)
+        {
+            return true;
+        }
+        if path.extension().is_some() {
+            return false;
+        }
+
+        let head = match read_head(path) {
+            Ok(v) => v,
+            Err(_) => return false,
+        };
+        let inner = self.inner.read().unwrap();
+        detect::shebang(&head)
+            .or_else(|| detect::modeline(&head))
+            .is_some_and(|v| inner.language_table.contains_key(v))
+    }
+}
+
+/// Compile file associations, builtin ones are appended.
+///
+/// # Arguments
+///
+/// + `table` - Associations configured by user.
+///
+/// # Returns
+///
+/// + All associations, and their compiled patterns.
+fn compile_associations(
+    mut table: Vec<Association>,
+) -> crate::Result<(Vec<Association>, globset::GlobSet)> {
+    table.extend(
+        [("*.c", "C"), ("*.h", "C")].map(|(pattern, language)| Association {
+            pattern: pattern.to_string(),
+            language: language.to_string(),
+            builtin: true,
+        }),
+    );
+
+    let patterns: Vec<_> = table.iter().map(|v| v.pattern.as_str()).collect();
+    let globs = crate::settings::compile_globs(&patterns)?;
+    Ok((table, globs))
+}
+
+/// Turn a shebang into a comment, positions are kept.
+///
+/// # Arguments
+///
+/// + `source` - The content of the source file.
+///
+/// # Returns
+///
+/// + The content to parse.
+fn mask_shebang(source: &str) -> std::borrow::Cow<'_, str> {
+    match source.strip_prefix("#!") {
+        Some(v) => std::borrow::Cow::Owned(format!("//{}", v)),
+        None => std::borrow::Cow::Borrowed(source),
     }
 }
+
+/// Read the start of a file as text.
+///
+/// # Arguments
+///
+/// + `path` - The path of the file.
+fn read_head(path: &std::path::Path) -> std::io::Result<String> {
+    use std::io::Read;
+
+    let mut content = Vec::new();
+    std::fs::File::open(path)?
+        .take(HEAD_SIZE)
+        .read_to_end(&mut content)?;
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
@@ -0,0 +1,205 @@
+/// Threads that move messages between the connection and the stream.
+pub enum IoThreads {
+    /// Transports provided by `lsp_server`.
+    Lsp(lsp_server::IoThreads),
+
+    /// Transport over any other stream.
+    Stream {
+        reader: std::thread::JoinHandle<std::io::Result<()>>,
+        writer: std::thread::JoinHandle<std::io::Result<()>>,
+    },
+}
+
+impl IoThreads {
+    /// Wait for the threads to exit.
+    pub fn join(self) -> std::io::Result<()> {
+        match self {
+            IoThreads::Lsp(v) => v.join(),
+            IoThreads::Stream { reader, writer } => {
+                for handle in [reader, writer] {
+                    match handle.join() {
+                        Ok(v) => v?,
+                        Err(e) => std::panic::resume_unwind(e),
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Open the communication channel selected by configuration.
+///
+/// # Arguments
+///
+/// + `config` - The LSP configuration.
+///
+/// # Returns
+///
+/// + The connection to the client, and the threads serving it.
+pub fn open(config: &crate::LspConfig) -> crate::Result<(lsp_server::Connection, IoThreads)> {
+    if config.stdio {
+        let (conn, threads) = lsp_server::Connection::stdio();
+        return Ok((conn, IoThreads::Lsp(threads)));
+    }
+
+    if let Some(port) = config.port {
+        tracing::info!("connect to 127.0.0.1:{}", port);
+        let (conn, threads) = lsp_server::Connection::connect(("127.0.0.1", port))?;
+        return Ok((conn, IoThreads::Lsp(threads)));
+    }
+
+    if let Some(path) = &config.pipe {
+        tracing::info!("connect to {}", path);
+        return connect_unix(std::path::Path::new(path));
+    }
+
+    if let Some(addr) = &config.listen {
+        return listen(addr);
+    }
+
+    Err(crate::Error::Config(String::from(
+        "one of --stdio, --port, --pipe or --listen is required",
+    )))
+}
+
+/// Accept one connection on a TCP port or a Unix domain socket.
+///
+/// # Arguments
+///
+/// + `addr` - A port, `HOST:PORT`, or the path of a Unix domain socket.
+///
+/// # Returns
+///
+/// + The connection to the client, and the threads serving it.
+fn listen(addr: &str) -> crate::Result<(lsp_server::Connection, IoThreads)> {
+    let tcp = match addr.parse::<u16>() {
+        Ok(port) => Some(std::net::SocketAddr::from(([127, 0, 0, 1], port))),
+        Err(_) => addr.parse::<std::net::SocketAddr>().ok(),
+    };
+
+    match tcp {
+        Some(addr) => {
+            tracing::info!("listen on {}", addr);
+            let (conn, threads) = lsp_server::Connection::listen(addr)?;
+            Ok((conn, IoThreads::Lsp(threads)))
+        }
+        None => listen_unix(std::path::Path::new(addr)),
+    }
+}
+
+/// Connect to a Unix domain socket.
+///
+/// # Arguments
+///
+/// + `path` - The path of the socket.
+#[cfg(unix)]
+fn connect_unix(path: &std::path::Path) -> crate::Result<(lsp_server::Connection, IoThreads)> {
+    let stream = std::os::unix::net::UnixStream::connect(path)?;
+    Ok(stream_transport(stream.try_clone()?, stream))
+}
+
+#[cfg(not(unix))]
+fn connect_unix(_path: &std::path::Path) -> crate::Result<(lsp_server::Connection, IoThreads)> {
+    Err(crate::Error::Config(String::from(
+        "--pipe is not supported on this platform",
+    )))
+}
+
+/// Accept one connection on a Unix domain socket.
+///
+/// A stale socket file left by a previous run is replaced, one still accepting
+/// connections is kept. The socket file is removed once the client connects.
+///
+/// # Arguments
+///
+/// + `path` - The path of the socket.
+#[cfg(unix)]
+fn listen_unix(path: &std::path::Path) -> crate::Result<(lsp_server::Connection, IoThreads)> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(crate::Error::Config(format!(
+                "{} exists and is not a socket",
+                path.display()
+            )));
+        }
+
+        // Nobody listens on a stale socket.
+        match std::os::unix::net::UnixStream::connect(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                std::fs::remove_file(path)?
+            }
+            Ok(_) => {
+                return Err(crate::Error::Config(format!(
+                    "{} is in use",
+                    path.display()
+                )))
+            }
+            Err(e) => {
+                return Err(crate::Error::Config(format!(
+                    "{} can not be replaced: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        }
+    }
+
+    tracing::info!("listen on {}", path.display());
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    let (stream, _) = listener.accept()?;
+    std::fs::remove_file(path).ok();
+
+    Ok(stream_transport(stream.try_clone()?, stream))
+}
+
+#[cfg(not(unix))]
+fn listen_unix(path: &std::path::Path) -> crate::Result<(lsp_server::Connection, IoThreads)> {
+    Err(crate::Error::Config(format!(
+        "{} is not a TCP address, and Unix domain sockets are not supported on this platform",
+        path.display()
+    )))
+}
+
+/// Serve a connection over a stream.
+///
+/// # Arguments
+///
+/// + `reader` - The read half of the stream.
+/// + `writer` - The write half of the stream.
+///
+/// # Returns
+///
+/// + The connection to the client, and the threads serving it.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn stream_transport<R, W>(reader: R, mut writer: W) -> (lsp_server::Connection, IoThreads)
+where
+    R: std::io::Read + Send + 'static,
+    W: std::io::Write + Send + 'static,
+{
+    let (reader_sender, receiver) = crossbeam_channel::bounded::<lsp_server::Message>(0);
+    let reader = std::thread::spawn(move || {
+        let mut reader = std::io::BufReader::new(reader);
+        while let Some(msg) = lsp_server::Message::read(&mut reader)? {
+            let is_exit =
+                matches!(&msg, lsp_server::Message::Notification(v) if v.method == "exit");
+            if reader_sender.send(msg).is_err() || is_exit {
+                break;
+            }
+        }
+        Ok(())
+    });
+
+    let (sender, writer_receiver) = crossbeam_channel::bounded::<lsp_server::Message>(0);
+    let writer = std::thread::spawn(move || {
+        for msg in writer_receiver {
+            msg.write(&mut writer)?;
+        }
+        Ok(())
+    });
+
+    let conn = lsp_server::Connection { sender, receiver };
+    (conn, IoThreads::Stream { reader, writer })
+}
@@ -0,0 +1,169 @@
+/// Score how well a name matches a fuzzy query.
+///
+/// From best to worst: exact match, prefix match, segment match (each query character
+/// starts a new segment in order, e.g. `gtd` for `get_tag_data` or `getTagData`),
+/// substring match and subsequence match. Lower case query matches case insensitively.
+///
+/// # Arguments
+///
+/// + `query` - The query string.
+/// + `name` - The name to match.
+///
+/// # Returns
+///
+/// + The score, higher is better, or `None` if not match.
+pub fn score(query: &str, name: &str) -> Option<i64> {
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    // Smart case: only a query with upper case letters is case sensitive.
+    let ignore_case = !query.chars().any(|c| c.is_uppercase());
+    let fold = |s: &str| match ignore_case {
+        true => s.to_lowercase(),
+        false => s.to_string(),
+    };
+    let q = fold(query);
+    let n = fold(name);
+    let len_penalty = (name.len() as i64 - query.len() as i64).abs();
+
+    if name == query {
+        return Some(1000);
+    }
+    if n == q {
+        return Some(950);
+    }
+    if n.starts_with(&q) {
+        return Some(800 - len_penalty);
+    }
+    if let Some(gaps) = segment_match(&q, name, ignore_case) {
+        return Some(700 - gaps * 10 - len_penalty);
+    }
+    if let Some(pos) = n.find(&q) {
+        // A substring at segment start is almost as good as a prefix.
+        let bonus = match segments(name).iter().any(|(beg, _)| *beg == pos) {
+            true => 100,
+            false => 0,
+        };
+        return Some(500 + bonus - pos as i64 - len_penalty);
+    }
+    subsequence_gaps(&q, &n).map(|gaps| 300 - gaps * 5 - len_penalty)
+}
+
+/// Split a name into segments at `_` and camelCase boundaries.
+///
+/// # Arguments
+///
+/// + `name` - The name.
+///
+/// # Returns
+///
+/// + List of segments, as pairs of begin and end byte offset.
+pub fn segments(name: &str) -> Vec<(usize, usize)> {
+    let mut ret = Vec::new();
+    let mut beg: Option<usize> = None;
+    let mut prev: Option<char> = None;
+
+    for (i, c) in name.char_indices() {
+        if c == '_' {
+            if let Some(b) = beg.take() {
+                ret.push((b, i));
+            }
+        } else {
+            let boundary = match prev {
+                Some(p) => {
+                    (p.is_lowercase() && c.is_uppercase())
+                        || (p.is_ascii_digit() != c.is_ascii_digit())
+                }
+                None => false,
+            };
+            match beg {
+                Some(b) if boundary => {
+                    ret.push((b, i));
+                    beg = Some(i);
+                }
+                Some(_) => {}
+                None => beg = Some(i),
+            }
+        }
+        prev = Some(c);
+    }
+    if let Some(b) = beg {
+        ret.push((b, name.len()));
+    }
+
+    ret
+}
+
+/// Match query against segments of name, each segment may consume a prefix of the query.
+///
+/// # Arguments
+///
+/// + `query` - The query string, already case folded.
+/// + `name` - The name.
+/// + `ignore_case` - Whether to match case insensitively.
+///
+/// # Returns
+///
+/// + The number of skipped segments, or `None` if not match.
+fn segment_match(query: &str, name: &str, ignore_case: bool) -> Option<i64> {
+    let query: Vec<char> = query.chars().collect();
+    let mut pos = 0;
+    let mut skipped = 0;
+
+    for (beg, end) in segments(name) {
+        if pos == query.len() {
+            break;
+        }
+
+        let seg: Vec<char> = match ignore_case {
+            true => name[beg..end].to_lowercase().chars().collect(),
+            false => name[beg..end].chars().collect(),
+        };
+        let common = seg
+            .iter()
+            .zip(&query[pos..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        match common {
+            0 => skipped += 1,
+            v => pos += v,
+        }
+    }
+
+    match pos == query.len() {
+        true => Some(skipped),
+        false => None,
+    }
+}
+
+/// Match query as a subsequence of name.
+///
+/// # Arguments
+///
+/// + `query` - The query string.
+/// + `name` - The name.
+///
+/// # Returns
+///
+/// + The number of characters between matched ones, or `None` if not match.
+fn subsequence_gaps(query: &str, name: &str) -> Option<i64> {
+    let mut chars = name.chars();
+    let mut gaps = 0;
+    let mut started = false;
+
+    for q in query.chars() {
+        loop {
+            let c = chars.next()?;
+            if c == q {
+                started = true;
+                break;
+            }
+            if started {
+                gaps += 1;
+            }
+        }
+    }
+
+    Some(gaps)
+}
@@ -1 +1,22 @@
+pub mod fuzzy;
 pub mod path;
+pub mod position;
+
+/// Get the message of a panic.
+///
+/// # Arguments
+///
+/// + `payload` - The payload caught by `std::panic::catch_unwind`.
+///
+/// # Returns
+///
+/// + The message.
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(v) = payload.downcast_ref::<&str>() {
+        return v.to_string();
+    }
+    match payload.downcast_ref::<String>() {
+        Some(v) => v.clone(),
+        None => String::from("unknown panic"),
+    }
+}
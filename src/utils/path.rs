@@ -13,18 +13,19 @@ pub fn walk_with_gitignore(path: std::path::PathBuf) -> std::io::Result<Vec<crat
     for ret in ignore::Walk::new(path) {
         match ret {
             Ok(e) => {
-                let path = e.path().to_path_buf();
-                let metadata = e.metadata().unwrap();
-                let mtime = metadata.modified()?;
-                let mtime = mtime.duration_since(std::time::UNIX_EPOCH).unwrap();
-                let mtime = mtime.as_secs();
-
+                // A file that cannot be read is skipped, not the whole folder.
+                let metadata = match e.metadata() {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::warn!("{}", e);
+                        continue;
+                    }
+                };
                 if metadata.is_file() {
-                    files_info.push(crate::db::FileInfo {
-                        path: path,
-                        mtime: mtime as i64,
-                        ..Default::default()
-                    });
+                    match file_info(e.path(), &metadata) {
+                        Ok(v) => files_info.push(v),
+                        Err(err) => tracing::warn!("{}: {}", e.path().display(), err),
+                    }
                 }
             }
             Err(e) => tracing::error!("ERROR: {}", e),
@@ -33,3 +34,276 @@ pub fn walk_with_gitignore(path: std::path::PathBuf) -> std::io::Result<Vec<crat
 
     Ok(files_info)
 }
+
+/// Ignore rules of one directory.
+#[derive(Debug)]
+struct DirRules {
+    /// Rules of `.ignore`, apply everywhere.
+    ignore: ignore::gitignore::Gitignore,
+
+    /// Rules of `.gitignore`, apply in git repositories.
+    gitignore: ignore::gitignore::Gitignore,
+
+    /// Rules of `.git/info/exclude`, if the directory is a git repository.
+    exclude: ignore::gitignore::Gitignore,
+
+    /// Whether the directory is a git repository.
+    has_git: bool,
+}
+
+impl DirRules {
+    fn new(dir: &std::path::Path) -> DirRules {
+        let build = |files: &[std::path::PathBuf]| {
+            let mut builder = ignore::gitignore::GitignoreBuilder::new(dir);
+            for file in files.iter().filter(|v| v.is_file()) {
+                if let Some(e) = builder.add(file) {
+                    tracing::warn!("{}", e);
+                }
+            }
+            builder
+                .build()
+                .unwrap_or_else(|_| ignore::gitignore::Gitignore::empty())
+        };
+
+        // A worktree or submodule has a `.git` file pointing to the git directory.
+        let git = dir.join(".git");
+        let git_dir = match std::fs::read_to_string(&git) {
+            Ok(v) => v
+                .strip_prefix("gitdir:")
+                .map(|v| dir.join(v.trim()))
+                .unwrap_or(git.clone()),
+            Err(_) => git.clone(),
+        };
+
+        DirRules {
+            ignore: build(&[dir.join(".ignore")]),
+            gitignore: build(&[dir.join(".gitignore")]),
+            exclude: build(&[git_dir.join("info/exclude")]),
+            has_git: git.exists(),
+        }
+    }
+}
+
+/// The rules `walk_with_gitignore` skips entries of a folder by, to check single paths.
+///
+/// Hidden entries are skipped. `.ignore` files apply in the folder and its parents. In a
+/// git repository `.gitignore` files up to the repository root, `.git/info/exclude` and
+/// the global excludes file apply too. Rules of directories are read once and cached.
+#[derive(Debug)]
+pub struct IgnoreRules {
+    /// The folder.
+    root: std::path::PathBuf,
+
+    /// Rules of the global excludes file of git.
+    global: ignore::gitignore::Gitignore,
+
+    /// Rules of directories, by path.
+    dirs: std::collections::HashMap<std::path::PathBuf, DirRules>,
+}
+
+impl IgnoreRules {
+    /// Create rules of a folder.
+    ///
+    /// # Arguments
+    ///
+    /// + `root` - Path to the folder.
+    pub fn new(root: &std::path::Path) -> IgnoreRules {
+        let (global, err) = ignore::gitignore::Gitignore::global();
+        if let Some(e) = err {
+            tracing::warn!("{}", e);
+        }
+
+        IgnoreRules {
+            root: root.to_path_buf(),
+            global,
+            dirs: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Get the folder.
+    pub fn root(&self) -> &std::path::Path {
+        &self.root
+    }
+
+    /// Forget cached rules that a changed path may affect.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The changed path.
+    pub fn changed(&mut self, path: &std::path::Path) {
+        let name = path.file_name().and_then(|v| v.to_str());
+        if matches!(name, Some(".ignore" | ".gitignore")) {
+            if let Some(dir) = path.parent() {
+                self.dirs.remove(dir);
+            }
+        }
+
+        // Repositories and their exclude files.
+        if let Some(i) = path.components().position(|v| v.as_os_str() == ".git") {
+            let dir: std::path::PathBuf = path.components().take(i).collect();
+            self.dirs.remove(&dir);
+        }
+    }
+
+    /// Check whether a path is skipped by `walk_with_gitignore`.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - Path to check.
+    ///
+    /// # Returns
+    ///
+    /// + `true` if the path is ignored, `false` if not or not under the folder.
+    pub fn is_ignored(&mut self, path: &std::path::Path) -> bool {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(v) => v.to_path_buf(),
+            Err(_) => return false,
+        };
+
+        // Entries in an ignored directory are never visited.
+        let mut entry = self.root.clone();
+        for name in relative.components() {
+            entry.push(name);
+            if name.as_os_str().to_string_lossy().starts_with('.') {
+                return true;
+            }
+            let is_dir = entry != path || path.is_dir();
+            if self.matched(&entry, is_dir).is_ignore() {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Match an entry against rules of its parent directories, the deepest match wins.
+    ///
+    /// # Arguments
+    ///
+    /// + `entry` - Path to the entry.
+    /// + `is_dir` - Whether the entry is a directory.
+    fn matched(&mut self, entry: &std::path::Path, is_dir: bool) -> ignore::Match<()> {
+        let dirs: Vec<_> = entry.ancestors().skip(1).map(|v| v.to_path_buf()).collect();
+        for dir in &dirs {
+            if !self.dirs.contains_key(dir) {
+                self.dirs.insert(dir.clone(), DirRules::new(dir));
+            }
+        }
+        let rules: Vec<_> = dirs.iter().map(|v| &self.dirs[v]).collect();
+
+        let any_git = rules.iter().any(|v| v.has_git);
+        let mut saw_git = false;
+        let (mut ignore, mut gitignore, mut exclude) = (
+            ignore::Match::None,
+            ignore::Match::None,
+            ignore::Match::None,
+        );
+        for rule in &rules {
+            if ignore.is_none() {
+                ignore = rule.ignore.matched(entry, is_dir).map(|_| ());
+            }
+            if any_git && !saw_git && gitignore.is_none() {
+                gitignore = rule.gitignore.matched(entry, is_dir).map(|_| ());
+            }
+            if any_git && !saw_git && exclude.is_none() {
+                exclude = rule.exclude.matched(entry, is_dir).map(|_| ());
+            }
+            saw_git = saw_git || rule.has_git;
+        }
+        let global = match any_git {
+            true => self.global.matched(entry, is_dir).map(|_| ()),
+            false => ignore::Match::None,
+        };
+
+        ignore.or(gitignore).or(exclude).or(global)
+    }
+}
+
+/// Build file information from metadata.
+///
+/// # Arguments
+///
+/// + `path` - Path to the file.
+/// + `metadata` - Metadata of the file.
+///
+/// # Returns
+///
+/// + File information, the hash is not computed.
+pub fn file_info(
+    path: &std::path::Path,
+    metadata: &std::fs::Metadata,
+) -> std::io::Result<crate::db::FileInfo> {
+    let mtime = metadata.modified()?;
+    let mtime = mtime
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    Ok(crate::db::FileInfo {
+        path: path.to_path_buf(),
+        size: metadata.len() as i64,
+        mtime: mtime.as_nanos() as i64,
+        ..Default::default()
+    })
+}
+
+/// Hash the content of a file.
+///
+/// # Arguments
+///
+/// + `data` - The content.
+///
+/// # Returns
+///
+/// + The hash.
+pub fn content_hash(data: &[u8]) -> i64 {
+    xxhash_rust::xxh3::xxh3_64(data) as i64
+}
+
+/// Hash a file on disk.
+///
+/// # Arguments
+///
+/// + `path` - Path to the file.
+///
+/// # Returns
+///
+/// + The hash.
+pub fn file_hash(path: &std::path::Path) -> std::io::Result<i64> {
+    Ok(content_hash(&std::fs::read(path)?))
+}
+
+/// Resolve the path of an include directive.
+///
+/// # Arguments
+///
+/// + `from` - The file that contains the include directive.
+/// + `include` - The included path as written in source.
+/// + `system` - Whether it is a `<system>` include.
+/// + `search_dirs` - Directories to search after the directory of `from`.
+///
+/// # Returns
+///
+/// + The path of included file, or `None` if not found.
+pub fn resolve_include(
+    from: &std::path::Path,
+    include: &str,
+    system: bool,
+    search_dirs: &[std::path::PathBuf],
+) -> Option<std::path::PathBuf> {
+    let include = std::path::Path::new(include);
+    if include.is_absolute() {
+        return include.is_file().then(|| include.to_path_buf());
+    }
+
+    // Quoted include searches the directory of current file first.
+    let current = match system {
+        true => None,
+        false => from.parent(),
+    };
+
+    current
+        .into_iter()
+        .chain(search_dirs.iter().map(|v| v.as_path()))
+        .map(|dir| dir.join(include))
+        .find(|v| v.is_file())
+}
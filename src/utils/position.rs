@@ -0,0 +1,107 @@
+/// The unit of columns in LSP positions.
+///
+/// Columns in database and syntax trees are always in bytes, they are converted from
+/// and to the encoding negotiated with the client at the protocol boundary.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PositionEncoding {
+    /// Columns count bytes.
+    Utf8,
+
+    /// Columns count UTF-16 code units, the LSP default.
+    #[default]
+    Utf16,
+
+    /// Columns count unicode characters.
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Pick the encoding from client capabilities.
+    ///
+    /// The first encoding the client prefers wins, UTF-16 if none is supported.
+    ///
+    /// # Arguments
+    ///
+    /// + `capabilities` - The capabilities of the client.
+    ///
+    /// # Returns
+    ///
+    /// + The encoding.
+    pub fn negotiate(capabilities: &lsp_types::ClientCapabilities) -> PositionEncoding {
+        capabilities
+            .general
+            .as_ref()
+            .and_then(|v| v.position_encodings.as_ref())
+            .into_iter()
+            .flatten()
+            .find_map(|v| match v.as_str() {
+                "utf-8" => Some(PositionEncoding::Utf8),
+                "utf-16" => Some(PositionEncoding::Utf16),
+                "utf-32" => Some(PositionEncoding::Utf32),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get the LSP kind of the encoding.
+    pub fn kind(self) -> lsp_types::PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => lsp_types::PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => lsp_types::PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => lsp_types::PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// Convert a column in bytes into this encoding.
+    ///
+    /// # Arguments
+    ///
+    /// + `line` - The text of the line.
+    /// + `column` - The column in bytes.
+    ///
+    /// # Returns
+    ///
+    /// + The column in this encoding, clamped to the line.
+    pub fn encode_column(self, line: &str, column: usize) -> u32 {
+        let mut end = column.min(line.len());
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let prefix = &line[..end];
+        let units = match self {
+            PositionEncoding::Utf8 => prefix.len(),
+            PositionEncoding::Utf16 => prefix.encode_utf16().count(),
+            PositionEncoding::Utf32 => prefix.chars().count(),
+        };
+        units as u32
+    }
+
+    /// Convert a column in this encoding into bytes.
+    ///
+    /// # Arguments
+    ///
+    /// + `line` - The text of the line.
+    /// + `column` - The column in this encoding.
+    ///
+    /// # Returns
+    ///
+    /// + The column in bytes, clamped to the line. A column inside a character points
+    ///   to the start of the character.
+    pub fn decode_column(self, line: &str, column: u32) -> usize {
+        let column = column as usize;
+        let mut units = 0;
+        for (i, c) in line.char_indices() {
+            let width = match self {
+                PositionEncoding::Utf8 => c.len_utf8(),
+                PositionEncoding::Utf16 => c.len_utf16(),
+                PositionEncoding::Utf32 => 1,
+            };
+            if units + width > column {
+                return i;
+            }
+            units += width;
+        }
+        line.len()
+    }
+}
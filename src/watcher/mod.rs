@@ -0,0 +1,169 @@
+/// Quiet time after the last event before changes are indexed.
+const DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Max time changes wait for a quiet moment, so a long burst still gets indexed.
+const MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Server side recursive file watcher over workspace folders.
+///
+/// For clients that do not watch files. Bursts of events are merged, and changed paths
+/// are queued on the indexer worker, the same as file operations of the client.
+#[derive(Debug, Default, Clone)]
+pub struct Watcher {
+    /// The file system watcher, dropped to stop watching.
+    watcher: std::sync::Arc<std::sync::Mutex<Option<notify::RecommendedWatcher>>>,
+
+    /// The watched workspace folders, with their ignore rules.
+    roots: std::sync::Arc<std::sync::Mutex<Vec<crate::utils::path::IgnoreRules>>>,
+
+    /// The thread that merges events and queues changed paths.
+    handle: std::sync::Arc<std::sync::Mutex<Option<std::thread::JoinHandle<()>>>>,
+}
+
+impl Watcher {
+    pub fn new() -> Watcher {
+        Watcher::default()
+    }
+
+    /// Start watching all workspace folders.
+    ///
+    /// # Arguments
+    ///
+    /// + `rt` - The runtime.
+    pub fn start(&self, rt: &crate::LspRuntime) -> notify::Result<()> {
+        use notify::Watcher;
+
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            sender.send(event).ok();
+        })?;
+
+        let roots: Vec<_> = rt
+            .workspace_folders
+            .iter()
+            .filter_map(|v| v.uri.to_file_path().ok())
+            .collect();
+        for root in &roots {
+            watcher.watch(root, notify::RecursiveMode::Recursive)?;
+        }
+        *self.roots.lock().unwrap() = roots
+            .iter()
+            .map(|v| crate::utils::path::IgnoreRules::new(v))
+            .collect();
+
+        let indexer = rt.indexer.clone();
+        let shared_roots = self.roots.clone();
+        let handle = std::thread::spawn(move || {
+            while let Some(paths) = debounce(&receiver) {
+                // Changed ignore files apply to the same batch.
+                let mut roots = shared_roots.lock().unwrap();
+                for root in roots.iter_mut() {
+                    paths.iter().for_each(|v| root.changed(v));
+                }
+                let paths: Vec<_> = paths
+                    .into_iter()
+                    .filter(|path| {
+                        roots.iter().any(|root| path.starts_with(root.root()))
+                            && !roots.iter_mut().any(|root| root.is_ignored(path))
+                    })
+                    .collect();
+                drop(roots);
+                if paths.is_empty() {
+                    continue;
+                }
+
+                tracing::debug!("watcher: {} paths changed", paths.len());
+                indexer.refresh(paths);
+            }
+        });
+
+        *self.watcher.lock().unwrap() = Some(watcher);
+        *self.handle.lock().unwrap() = Some(handle);
+
+        Ok(())
+    }
+
+    /// Watch a folder added to workspace. Does nothing if the watcher is not started.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the folder.
+    pub fn add_folder(&self, path: &std::path::Path) -> notify::Result<()> {
+        use notify::Watcher;
+
+        if let Some(watcher) = self.watcher.lock().unwrap().as_mut() {
+            watcher.watch(path, notify::RecursiveMode::Recursive)?;
+            self.roots
+                .lock()
+                .unwrap()
+                .push(crate::utils::path::IgnoreRules::new(path));
+        }
+
+        Ok(())
+    }
+
+    /// Stop watching a folder removed from workspace.
+    ///
+    /// # Arguments
+    ///
+    /// + `path` - The path of the folder.
+    pub fn remove_folder(&self, path: &std::path::Path) -> notify::Result<()> {
+        use notify::Watcher;
+
+        if let Some(watcher) = self.watcher.lock().unwrap().as_mut() {
+            self.roots.lock().unwrap().retain(|v| v.root() != path);
+            watcher.unwatch(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop watching and wait for pending changes to be queued.
+    pub fn stop(&self) {
+        // Dropping the watcher disconnects the channel, which ends the thread.
+        self.watcher.lock().unwrap().take();
+
+        let handle = self.handle.lock().unwrap().take();
+        if let Some(handle) = handle {
+            handle.join().ok();
+        }
+    }
+}
+
+/// Wait for a burst of events to settle.
+///
+/// # Arguments
+///
+/// + `receiver` - Events from the file system watcher.
+///
+/// # Returns
+///
+/// + Changed paths, or `None` if the watcher is stopped.
+fn debounce(
+    receiver: &crossbeam_channel::Receiver<notify::Result<notify::Event>>,
+) -> Option<std::collections::HashSet<std::path::PathBuf>> {
+    let mut paths = std::collections::HashSet::new();
+
+    let mut event = receiver.recv().ok()?;
+    let deadline = std::time::Instant::now() + MAX_DELAY;
+    loop {
+        match event {
+            Ok(v) if !v.kind.is_access() => paths.extend(v.paths),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("watcher: {}", e),
+        }
+
+        let timeout = deadline
+            .saturating_duration_since(std::time::Instant::now())
+            .min(DEBOUNCE);
+        if timeout.is_zero() {
+            break;
+        }
+        event = match receiver.recv_timeout(timeout) {
+            Ok(v) => v,
+            Err(_) => break,
+        };
+    }
+
+    Some(paths)
+}
@@ -5,7 +5,9 @@ struct LspClientInner {
     listener: std::net::TcpListener,
     id: u32,
     stream: Option<std::net::TcpStream>,
-    recvbuf: String,
+    recvbuf: Vec<u8>,
+    notifications: Vec<serde_json::Value>,
+    responses: Vec<serde_json::Value>,
 }
 
 impl LspClientInner {
@@ -24,26 +26,96 @@ impl LspClientInner {
         method: &str,
         params: serde_json::Value,
     ) -> std::io::Result<serde_json::Value> {
-        // Construct the payload.
+        let id = self.send_request(method, params)?;
+        let rsp = self.recv_response(id)?;
+
+        let obj = rsp.as_object().unwrap();
+        if !obj.contains_key("result") {
+            return Err(std::io::Error::other(rsp["error"].to_string()));
+        }
+
+        Ok(rsp["result"].clone())
+    }
+
+    /// Send request without waiting for response.
+    ///
+    /// # Arguments
+    ///
+    /// + `method` - Method name.
+    /// + `params` - Method parameters.
+    ///
+    /// # Returns
+    ///
+    /// + Request id.
+    pub fn send_request(
+        &mut self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> std::io::Result<u32> {
+        let id = self.id;
         let mut msg = self.build_message(method, params);
-        msg["id"] = self.id.into();
+        msg["id"] = id.into();
         self.id += 1;
 
-        // Send request.
         self.send(&msg)?;
 
-        // Rece response.
-        let rsp = self.recv().unwrap();
+        Ok(id)
+    }
+
+    /// Receive the response of a request, responses may come in any order.
+    ///
+    /// # Arguments
+    ///
+    /// + `id` - Request id.
+    ///
+    /// # Returns
+    ///
+    /// + Response message.
+    pub fn recv_response(&mut self, id: u32) -> std::io::Result<serde_json::Value> {
+        if let Some(idx) = self.responses.iter().position(|v| v["id"] == id) {
+            return Ok(self.responses.remove(idx));
+        }
 
-        let obj = rsp.as_object().unwrap();
-        if obj.contains_key("result") == false {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                rsp["error"].to_string(),
-            ));
+        // Notifications and other responses received before it are saved.
+        loop {
+            let msg = self.recv()?;
+            if msg.get("method").is_some() {
+                self.save_message(msg)?;
+            } else if msg["id"] == id {
+                return Ok(msg);
+            } else {
+                self.responses.push(msg);
+            }
         }
+    }
+
+    /// Receive responses in the order server sends them, until a message matches.
+    ///
+    /// # Arguments
+    ///
+    /// + `pred` - Returns `true` for the expected message.
+    ///
+    /// # Returns
+    ///
+    /// + Responses received before the expected message.
+    pub fn responses_until<F>(&mut self, pred: F) -> std::io::Result<Vec<serde_json::Value>>
+    where
+        F: Fn(&serde_json::Value) -> bool,
+    {
+        let mut responses = std::mem::take(&mut self.responses);
+        loop {
+            let msg = self.recv()?;
+            if msg.get("method").is_none() {
+                responses.push(msg);
+                continue;
+            }
 
-        return Ok(rsp["result"].clone());
+            let found = pred(&msg);
+            self.save_message(msg)?;
+            if found {
+                return Ok(responses);
+            }
+        }
     }
 
     /// Send notification.
@@ -59,6 +131,44 @@ impl LspClientInner {
         Ok(())
     }
 
+    /// Wait for a message sent by server.
+    ///
+    /// # Arguments
+    ///
+    /// + `pred` - Returns `true` for the expected message.
+    pub fn wait_for<F>(&mut self, pred: F) -> std::io::Result<()>
+    where
+        F: Fn(&serde_json::Value) -> bool,
+    {
+        if self.notifications.iter().any(&pred) {
+            return Ok(());
+        }
+
+        loop {
+            let msg = self.recv()?;
+            let found = pred(&msg);
+            self.save_message(msg)?;
+            if found {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Save a message sent by server, requests are answered with empty result.
+    ///
+    /// # Arguments
+    ///
+    /// + `msg` - Message.
+    fn save_message(&mut self, msg: serde_json::Value) -> std::io::Result<()> {
+        if let Some(id) = msg.get("id") {
+            let rsp = json!({ "jsonrpc": "2.0", "id": id, "result": null });
+            self.send(&rsp)?;
+        }
+        self.notifications.push(msg);
+
+        Ok(())
+    }
+
     /// Build message.
     ///
     /// # Arguments
@@ -74,7 +184,7 @@ impl LspClientInner {
         msg["method"] = method.into();
         msg["params"] = params;
 
-        return msg;
+        msg
     }
 
     /// Send message.
@@ -126,39 +236,35 @@ impl LspClientInner {
 
         // Wait unitil data contains the full header line.
         // https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#contentPart
-        loop {
+        let re = regex::bytes::Regex::new(r"Content-Length:\s*(\d+)\r\n\r\n").unwrap();
+        while !re.is_match(&self.recvbuf) {
             let read_sz = stream.read(&mut buffer)?;
-            let data = String::from_utf8_lossy(&buffer[..read_sz]).to_string();
-            self.recvbuf.push_str(data.as_str());
-
-            if self.recvbuf.contains("\r\n\r\n") {
-                break;
+            if read_sz == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
             }
+            self.recvbuf.extend_from_slice(&buffer[..read_sz]);
         }
 
         // Wait for payload
-        let re = regex::Regex::new(r"Content-Length:\s*(\d+)\r\n\r\n").unwrap();
-        let cap = re.captures(self.recvbuf.as_str()).unwrap();
-        let payload_sz = cap[1].parse::<usize>().unwrap();
+        let cap = re.captures(&self.recvbuf).unwrap();
+        let payload_sz = String::from_utf8_lossy(&cap[1]).parse::<usize>().unwrap();
 
-        let header_line = cap.get(0).unwrap();
-        let header_line_len = header_line.len();
-        self.recvbuf = self.recvbuf.chars().skip(header_line_len).collect();
+        let header_line_len = cap.get(0).unwrap().end();
+        self.recvbuf.drain(..header_line_len);
 
         while self.recvbuf.len() < payload_sz {
             let read_sz = stream.read(&mut buffer)?;
-            let data = String::from_utf8_lossy(&buffer[..read_sz]).to_string();
-            self.recvbuf.push_str(data.as_str());
+            if read_sz == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            self.recvbuf.extend_from_slice(&buffer[..read_sz]);
         }
 
-        // Parser response
-        let data = self.recvbuf.as_str()[..payload_sz].to_string();
-        let rsp: serde_json::Value = serde_json::from_str(&data)?;
-
-        // Remove payload from recvbuf.
-        self.recvbuf = self.recvbuf.chars().skip(payload_sz).collect();
+        // Parser response, and remove payload from recvbuf.
+        let data: Vec<u8> = self.recvbuf.drain(..payload_sz).collect();
+        let rsp: serde_json::Value = serde_json::from_slice(&data)?;
 
-        return Ok(rsp);
+        Ok(rsp)
     }
 }
 
@@ -178,17 +284,19 @@ impl LspClient {
         let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
 
         let inner = LspClientInner {
-            listener: listener,
+            listener,
             id: 1,
             stream: None,
-            recvbuf: "".into(),
+            recvbuf: Vec::new(),
+            notifications: Vec::new(),
+            responses: Vec::new(),
         };
 
         let client = LspClient {
             inner: std::sync::Arc::new(std::sync::Mutex::new(inner)),
         };
 
-        return Ok(client);
+        Ok(client)
     }
 
     /// Get local address.
@@ -198,11 +306,16 @@ impl LspClient {
     /// + Local address.
     pub fn local_addr(&self) -> std::net::SocketAddr {
         let inner = self.inner.lock().unwrap();
-        return inner.listener.local_addr().unwrap();
+        inner.listener.local_addr().unwrap()
     }
 
     /// Perform initialize request.
-    pub fn initialize(&mut self) -> std::io::Result<()> {
+    ///
+    /// # Arguments
+    ///
+    /// + `root` - Path to workspace root.
+    /// + `options` - Initialization options.
+    pub fn initialize(&mut self, root: &str, options: serde_json::Value) -> std::io::Result<()> {
         use lsp_types::Url;
 
         let mut inner = self.inner.lock().unwrap();
@@ -210,14 +323,27 @@ impl LspClient {
         let (stream, _) = inner.listener.accept()?;
         inner.stream = Some(stream);
 
-        let root_url = env!("CARGO_TARGET_TMPDIR");
-        let root_url = Url::from_file_path(root_url).unwrap();
+        let root_url = Url::from_file_path(root).unwrap();
 
         let param = json!({
             "rootUri": root_url.to_string(),
+            "initializationOptions": options,
             "capabilities": {
                 "general": {
                     "positionEncodings": [ "utf-8" ]
+                },
+                "window": {
+                    "workDoneProgress": true
+                },
+                "workspace": {
+                    "didChangeWatchedFiles": {
+                        "dynamicRegistration": true
+                    }
+                },
+                "textDocument": {
+                    "documentSymbol": {
+                        "hierarchicalDocumentSymbolSupport": true
+                    }
                 }
             }
         });
@@ -231,6 +357,121 @@ impl LspClient {
         Ok(())
     }
 
+    /// Send request and receive response.
+    ///
+    /// # Arguments
+    ///
+    /// + `method` - Method name.
+    /// + `params` - Method parameters.
+    ///
+    /// # Returns
+    ///
+    /// + Response result.
+    pub fn request(
+        &mut self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> std::io::Result<serde_json::Value> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.request(method, params)
+    }
+
+    /// Send request without waiting for response.
+    ///
+    /// # Arguments
+    ///
+    /// + `method` - Method name.
+    /// + `params` - Method parameters.
+    ///
+    /// # Returns
+    ///
+    /// + Request id.
+    pub fn send_request(
+        &mut self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> std::io::Result<u32> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.send_request(method, params)
+    }
+
+    /// Receive the response of a request sent by `send_request`.
+    ///
+    /// # Arguments
+    ///
+    /// + `id` - Request id.
+    ///
+    /// # Returns
+    ///
+    /// + Response message, with either `result` or `error`.
+    pub fn recv_response(&mut self, id: u32) -> std::io::Result<serde_json::Value> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.recv_response(id)
+    }
+
+    /// Send notification.
+    ///
+    /// # Arguments
+    ///
+    /// + `method` - Method name.
+    /// + `params` - Method parameters.
+    pub fn notify(&mut self, method: &str, params: serde_json::Value) -> std::io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.notify(method, params)
+    }
+
+    /// Wait for a message sent by server.
+    ///
+    /// # Arguments
+    ///
+    /// + `pred` - Returns `true` for the expected message.
+    pub fn wait_for<F>(&mut self, pred: F) -> std::io::Result<()>
+    where
+        F: Fn(&serde_json::Value) -> bool,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        inner.wait_for(pred)
+    }
+
+    /// Receive responses in the order server sends them, until a message matches.
+    ///
+    /// # Arguments
+    ///
+    /// + `pred` - Returns `true` for the expected message.
+    ///
+    /// # Returns
+    ///
+    /// + Responses received before the expected message.
+    pub fn responses_until<F>(&mut self, pred: F) -> std::io::Result<Vec<serde_json::Value>>
+    where
+        F: Fn(&serde_json::Value) -> bool,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        inner.responses_until(pred)
+    }
+
+    /// Wait until the server finishes indexing the workspace.
+    pub fn wait_indexed(&mut self) -> std::io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.wait_for(|v| {
+            v["method"] == "$/progress"
+                && v["params"]["token"]
+                    .as_str()
+                    .is_some_and(|t| t.starts_with("syntaxforest/indexing/"))
+                && v["params"]["value"]["kind"] == "end"
+        })
+    }
+
+    /// Take all notifications and requests received so far.
+    ///
+    /// # Returns
+    ///
+    /// + List of messages.
+    pub fn take_notifications(&mut self) -> Vec<serde_json::Value> {
+        let mut inner = self.inner.lock().unwrap();
+        std::mem::take(&mut inner.notifications)
+    }
+
     /// Perform shutdown request.
     pub fn shutdown(&mut self) -> std::io::Result<()> {
         let mut inner = self.inner.lock().unwrap();
@@ -246,7 +487,7 @@ impl LspClient {
 
     /// Close client.
     pub fn close(&mut self) -> std::io::Result<()> {
-        let mut inner = self.inner.lock().unwrap();
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
         inner.stream = None;
         Ok(())
     }
@@ -1,2 +1,3 @@
 pub mod asset;
 pub mod lsp_client;
+pub mod workspace;
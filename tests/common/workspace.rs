@@ -0,0 +1,182 @@
+/// Start a LSP server on a fresh copy of the sample workspace, and drive it with a client.
+///
+/// # Arguments
+///
+/// + `name` - Name of the workspace, must be unique across tests.
+/// + `f` - Client actions, called after the workspace is indexed and before shutdown.
+///
+/// # Returns
+///
+/// + Path to the workspace root.
+pub fn run<F>(name: &str, f: F) -> String
+where
+    F: FnOnce(&mut super::lsp_client::LspClient, &str) + Send + 'static,
+{
+    run_with(name, |_| {}, f)
+}
+
+/// Same as `run`, but allow to change the workspace before the server starts.
+///
+/// # Arguments
+///
+/// + `name` - Name of the workspace, must be unique across tests.
+/// + `setup` - Called with the workspace root after the sample is extracted.
+/// + `f` - Client actions, called after the workspace is indexed and before shutdown.
+///
+/// # Returns
+///
+/// + Path to the workspace root.
+pub fn run_with<S, F>(name: &str, setup: S, f: F) -> String
+where
+    S: FnOnce(&str),
+    F: FnOnce(&mut super::lsp_client::LspClient, &str) + Send + 'static,
+{
+    run_with_options(name, setup, serde_json::Value::Null, f)
+}
+
+/// Same as `run_with`, but with initialization options.
+///
+/// # Arguments
+///
+/// + `name` - Name of the workspace, must be unique across tests.
+/// + `setup` - Called with the workspace root after the sample is extracted.
+/// + `options` - Initialization options.
+/// + `f` - Client actions, called after the workspace is indexed and before shutdown.
+///
+/// # Returns
+///
+/// + Path to the workspace root.
+pub fn run_with_options<S, F>(name: &str, setup: S, options: serde_json::Value, f: F) -> String
+where
+    S: FnOnce(&str),
+    F: FnOnce(&mut super::lsp_client::LspClient, &str) + Send + 'static,
+{
+    let root = format!("{}/{}", env!("CARGO_TARGET_TMPDIR"), name);
+    std::fs::create_dir_all(&root).unwrap();
+    super::asset::Asset::cleanup_and_extract(&root).unwrap();
+    setup(&root);
+
+    start(name, options, f)
+}
+
+/// Start a LSP server listening on a free TCP port in another thread, and connect to it.
+///
+/// # Arguments
+///
+/// + `config` - The LSP configuration, `listen` is set to the port.
+///
+/// # Returns
+///
+/// + The connection, and the thread running the server.
+pub fn listen_tcp(
+    config: syntax_forest::LspConfig,
+) -> (std::net::TcpStream, std::thread::JoinHandle<()>) {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let config = syntax_forest::LspConfig {
+        listen: Some(port.to_string()),
+        ..config
+    };
+    let server = std::thread::spawn(move || syntax_forest::start_lsp(&config).unwrap());
+
+    // The server may not listen yet.
+    let stream = loop {
+        match std::net::TcpStream::connect(("127.0.0.1", port)) {
+            Ok(v) => break v,
+            Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+        }
+    };
+    (stream, server)
+}
+
+/// Start a LSP server again on a workspace left by `run`, the database is kept.
+///
+/// # Arguments
+///
+/// + `name` - Name of the workspace.
+/// + `f` - Client actions, called after the workspace is indexed and before shutdown.
+///
+/// # Returns
+///
+/// + Path to the workspace root.
+pub fn rerun<F>(name: &str, f: F) -> String
+where
+    F: FnOnce(&mut super::lsp_client::LspClient, &str) + Send + 'static,
+{
+    start(name, serde_json::Value::Null, f)
+}
+
+/// Start a LSP server on a workspace and drive it with a client.
+///
+/// # Arguments
+///
+/// + `name` - Name of the workspace.
+/// + `options` - Initialization options.
+/// + `f` - Client actions, called after the workspace is indexed and before shutdown.
+///
+/// # Returns
+///
+/// + Path to the workspace root.
+fn start<F>(name: &str, options: serde_json::Value, f: F) -> String
+where
+    F: FnOnce(&mut super::lsp_client::LspClient, &str) + Send + 'static,
+{
+    let root = format!("{}/{}", env!("CARGO_TARGET_TMPDIR"), name);
+    let dbfile_path = format!("{}/tags.db", root);
+
+    let mut client = super::lsp_client::LspClient::new().unwrap();
+    let port = client.local_addr().port();
+
+    let mut client_copy = client.clone();
+    let client_root = root.clone();
+    let thread_handle = std::thread::spawn(move || {
+        let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            client_copy.initialize(&client_root, options).unwrap();
+            client_copy.wait_indexed().unwrap();
+            f(&mut client_copy, &client_root);
+            client_copy.shutdown().unwrap();
+        }));
+
+        // Disconnect so the server does not wait forever for a failed client.
+        if ret.is_err() {
+            client_copy.close().unwrap();
+        }
+        ret
+    });
+
+    // Start lsp server.
+    let config = syntax_forest::LspConfig {
+        dbfile: Some(dbfile_path),
+        port: Some(port),
+        logdir: Some(root.clone()),
+        loglevel: Some("TRACE".into()),
+        ..Default::default()
+    };
+    syntax_forest::start_lsp(&config).unwrap();
+
+    if let Err(e) = thread_handle.join().unwrap() {
+        std::panic::resume_unwind(e);
+    }
+    client.close().unwrap();
+
+    root
+}
+
+/// Build a `file://` uri of a file in workspace.
+///
+/// # Arguments
+///
+/// + `root` - Path to workspace root.
+/// + `name` - File name relative to root.
+///
+/// # Returns
+///
+/// + The uri.
+pub fn uri(root: &str, name: &str) -> String {
+    lsp_types::Url::from_file_path(format!("{}/{}", root, name))
+        .unwrap()
+        .to_string()
+}
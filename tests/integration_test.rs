@@ -1,31 +1,1772 @@
 mod common;
 
+use serde_json::json;
+
 #[test]
 fn c_parser() {
-    let cargo_target_tmpdir = env!("CARGO_TARGET_TMPDIR");
-    let dbfile_path = format!("{}/tags.db", cargo_target_tmpdir);
+    let root = common::workspace::run("c_parser", |_, _| {});
+    let dbfile_path = format!("{}/tags.db", root);
+
+    // Check tags.
+    let db = rusqlite::Connection::open(&dbfile_path).unwrap();
+    let mut stmt = db
+        .prepare("SELECT type, name, name_beg_row, name_beg_col FROM tags ORDER BY id")
+        .unwrap();
+    let tags: Vec<(i64, String, i64, i64)> = stmt
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .unwrap()
+        .map(|v| v.unwrap())
+        .collect();
+
+    // (type, name, row, column)
+    let expect = [
+        (10, "ERROR_TABLE", 5, 8),
+        (5, "test_errno", 9, 13),
+        (7, "TEST_OK", 11, 4),
+        (10, "EXPAND_ERROR", 12, 8),
+        (6, "test_errno_t", 15, 2),
+        (3, "runtime", 17, 15),
+        (11, "dummy", 19, 9),
+        (6, "runtime_t", 20, 2),
+        (8, "s_rt", 22, 17),
+        (8, "s_help", 24, 19),
+        (2, "_add", 26, 11),
+        (1, "_add", 34, 11),
+        (1, "main", 54, 4),
+    ];
+    for (kind, name, row, col) in expect {
+        let tag = (kind, name.to_string(), row, col);
+        assert!(tags.contains(&tag), "missing tag {:?}", tag);
+    }
+
+    // Check xrefs.
+    let mut stmt = db
+        .prepare(
+            "SELECT x.type, x.name, x.beg_row, x.beg_col, t.name
+            FROM xrefs x LEFT JOIN tags t ON x.hold = t.id
+            ORDER BY x.id",
+        )
+        .unwrap();
+    let xrefs: Vec<(i64, String, i64, i64, Option<String>)> = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })
+        .unwrap()
+        .map(|v| v.unwrap())
+        .collect();
+
+    // (type, name, row, column, hold)
+    let expect = [
+        (3, "ERROR_TABLE", 13, 0, Some("test_errno")),
+        (2, "runtime_t", 22, 7, None),
+        (6, "i", 37, 9, Some("_add")),
+        (1, "sscanf", 40, 12, Some("_add")),
+        (5, "s_rt", 46, 8, Some("_add")),
+        (4, "dummy", 46, 13, Some("_add")),
+        (5, "s_help", 58, 32, Some("main")),
+        (1, "_add", 62, 11, Some("main")),
+    ];
+    for (kind, name, row, col, hold) in expect {
+        let xref = (kind, name.to_string(), row, col, hold.map(String::from));
+        assert!(xrefs.contains(&xref), "missing xref {:?}", xref);
+    }
+}
+
+#[test]
+fn goto_definition() {
+    common::workspace::run("goto_definition", |client, root| {
+        let uri = common::workspace::uri(root, "test.c");
+        let mut definition = |line: u32, character: u32| {
+            let params = json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character },
+            });
+            client.request("textDocument/definition", params).unwrap()
+        };
+
+        // Function call jumps to the definition instead of the prototype.
+        let rsp = definition(62, 11);
+        assert_eq!(rsp[0]["uri"], uri);
+        assert_eq!(
+            rsp[0]["range"]["start"],
+            json!({ "line": 34, "character": 11 })
+        );
+
+        // Global variable.
+        let rsp = definition(46, 8);
+        assert_eq!(
+            rsp[0]["range"]["start"],
+            json!({ "line": 22, "character": 17 })
+        );
+
+        // Include directive.
+        let rsp = definition(3, 12);
+        assert_eq!(rsp[0]["uri"], common::workspace::uri(root, "test.h"));
+
+        // Unknown symbol.
+        let rsp = definition(40, 12);
+        assert_eq!(rsp, json!([]));
+    });
+}
+
+#[test]
+fn references() {
+    common::workspace::run("references", |client, root| {
+        let uri = common::workspace::uri(root, "test.c");
+        let mut references = |line: u32, character: u32, include_declaration: bool| {
+            let params = json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": include_declaration },
+            });
+            let rsp = client.request("textDocument/references", params).unwrap();
+            rsp.as_array()
+                .unwrap()
+                .iter()
+                .map(|v| {
+                    (
+                        v["range"]["start"]["line"].as_u64().unwrap(),
+                        v["range"]["start"]["character"].as_u64().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Global variable.
+        assert_eq!(references(46, 8, false), vec![(46, 8), (49, 20)]);
+        assert_eq!(references(46, 8, true), vec![(22, 17), (46, 8), (49, 20)]);
+
+        // Function, from its definition.
+        assert_eq!(references(34, 11, true), vec![(26, 11), (34, 11), (62, 11)]);
+
+        // Local variable only in its function.
+        assert_eq!(
+            references(37, 16, false),
+            vec![(37, 9), (37, 16), (37, 26), (40, 24), (42, 59)]
+        );
+        assert_eq!(
+            references(37, 16, true),
+            vec![(36, 8), (37, 9), (37, 16), (37, 26), (40, 24), (42, 59)]
+        );
+
+        // Local variable, from its declaration.
+        assert_eq!(
+            references(36, 8, true),
+            vec![(36, 8), (37, 9), (37, 16), (37, 26), (40, 24), (42, 59)]
+        );
+
+        // Parameters of the same name in different functions.
+        assert_eq!(references(54, 13, true), vec![(54, 13), (56, 8), (62, 16)]);
+        assert_eq!(references(62, 16, false), vec![(56, 8), (62, 16)]);
+
+        // Partial results.
+        let params = json!({
+            "textDocument": { "uri": uri },
+            "position": { "line": 46, "character": 8 },
+            "context": { "includeDeclaration": true },
+            "partialResultToken": "partial",
+        });
+        let rsp = client.request("textDocument/references", params).unwrap();
+        assert_eq!(rsp, json!([]));
+
+        let notifications = client.take_notifications();
+        let partial: Vec<_> = notifications
+            .iter()
+            .filter(|v| v["method"] == "$/progress" && v["params"]["token"] == "partial")
+            .flat_map(|v| v["params"]["value"].as_array().unwrap().clone())
+            .collect();
+        assert_eq!(partial.len(), 3);
+    });
+}
+
+#[test]
+fn document_symbol() {
+    common::workspace::run("document_symbol", |client, root| {
+        let uri = common::workspace::uri(root, "test.c");
+        let params = json!({ "textDocument": { "uri": uri } });
+        let rsp = client
+            .request("textDocument/documentSymbol", params)
+            .unwrap();
+
+        let find = |name: &str| -> serde_json::Value {
+            rsp.as_array()
+                .unwrap()
+                .iter()
+                .find(|v| v["name"] == name)
+                .unwrap()
+                .clone()
+        };
+
+        // Enumerators under enum.
+        let e = find("test_errno");
+        assert_eq!(e["children"][0]["name"], "TEST_OK");
+
+        // Fields under struct.
+        let s = find("runtime");
+        assert_eq!(s["children"][0]["name"], "dummy");
+
+        // Function with parameters as detail.
+        let f = find("main");
+        assert_eq!(f["detail"], "(int argc, char* argv[])");
+        assert_eq!(
+            f["selectionRange"]["start"],
+            json!({ "line": 54, "character": 4 })
+        );
+
+        // Macros.
+        assert_eq!(find("ERROR_TABLE")["detail"], "(xx)");
+        find("test_errno_t");
+    });
+}
+
+#[test]
+fn workspace_symbol() {
+    common::workspace::run("workspace_symbol", |client, _| {
+        let mut search = |query: &str| -> Vec<(String, u64)> {
+            let params = json!({ "query": query });
+            let rsp = client.request("workspace/symbol", params).unwrap();
+            rsp.as_array()
+                .unwrap()
+                .iter()
+                .map(|v| {
+                    (
+                        v["name"].as_str().unwrap().to_string(),
+                        v["location"]["range"]["start"]["line"].as_u64().unwrap(),
+                    )
+                })
+                .collect()
+        };
+
+        // Exact match comes before prefix match.
+        let rsp = search("runtime");
+        assert_eq!(rsp[0].0, "runtime");
+        assert_eq!(rsp[1].0, "runtime_t");
+
+        // Substring at segment start, function definition before prototype.
+        let rsp = search("add");
+        assert_eq!(rsp[0], ("_add".to_string(), 34));
+        assert_eq!(rsp[1], ("_add".to_string(), 26));
+
+        // Segment match.
+        let rsp = search("tet");
+        assert_eq!(rsp[0].0, "test_errno_t");
+
+        // Short query matches segments after the first one.
+        let rsp = search("et");
+        assert!(rsp.iter().any(|v| v.0 == "test_errno_t"));
+
+        // Subsequence match, upper case query is case sensitive.
+        let rsp = search("ETBL");
+        assert_eq!(rsp, vec![("ERROR_TABLE".to_string(), 5)]);
+
+        assert!(search("xyz").is_empty());
+    });
+}
+
+#[test]
+fn workspace_symbol_ranking() {
+    let setup = |root: &str| {
+        let files = [
+            (
+                "names.c",
+                "int tag;\nint TAG;\nint tag_list;\nint to_a_gap;\nint toAlphaGo;\n\
+                int get_tag;\nint stage;\nint tiny_bag;\n",
+            ),
+            ("kinds.c", "int kw(void) { return 0; }\nint kw;\n"),
+            ("kinds.h", "int kw(void);\nstruct kw_s { int kw; };\n"),
+            ("near/main.c", "int px;\n"),
+            ("near/other.c", "int px;\n"),
+            ("near/sub/x.c", "int px;\n"),
+            ("far/a/b/y.c", "int px;\n"),
+        ];
+        for (name, content) in files {
+            let path = std::path::Path::new(root).join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+    };
+
+    common::workspace::run_with("workspace_symbol_ranking", setup, |client, root| {
+        let search = |client: &mut common::lsp_client::LspClient,
+                      query: &str|
+         -> Vec<(String, String, u64)> {
+            let params = json!({ "query": query });
+            let rsp = client.request("workspace/symbol", params).unwrap();
+            rsp.as_array()
+                .unwrap()
+                .iter()
+                .map(|v| {
+                    let uri = v["location"]["uri"].as_str().unwrap();
+                    (
+                        v["name"].as_str().unwrap().to_string(),
+                        uri.strip_prefix(&common::workspace::uri(root, ""))
+                            .unwrap()
+                            .trim_start_matches('/')
+                            .to_string(),
+                        v["location"]["range"]["start"]["line"].as_u64().unwrap(),
+                    )
+                })
+                .collect()
+        };
+
+        // Exact, case folded, prefix, segment, substring at segment start, substring and
+        // subsequence match.
+        let names: Vec<_> = search(client, "tag")
+            .into_iter()
+            .filter(|v| v.1 == "names.c")
+            .map(|v| v.0)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "tag",
+                "TAG",
+                "tag_list",
+                "to_a_gap",
+                "toAlphaGo",
+                "get_tag",
+                "stage",
+                "tiny_bag"
+            ]
+        );
+
+        // Upper case query is case sensitive.
+        let names: Vec<_> = search(client, "TAG").into_iter().map(|v| v.0).collect();
+        assert_eq!(names, vec!["TAG"]);
+
+        // Function, variable, prototype and then field.
+        let kinds: Vec<_> = search(client, "kw")
+            .into_iter()
+            .filter(|v| v.0 == "kw")
+            .map(|v| (v.1, v.2))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("kinds.c".to_string(), 0),
+                ("kinds.c".to_string(), 1),
+                ("kinds.h".to_string(), 0),
+                ("kinds.h".to_string(), 1),
+            ]
+        );
+
+        // The opened file, its directory, a child directory and then others.
+        let params = json!({
+            "textDocument": {
+                "uri": common::workspace::uri(root, "near/main.c"),
+                "languageId": "c",
+                "version": 1,
+                "text": "int px;\n",
+            }
+        });
+        client.notify("textDocument/didOpen", params).unwrap();
+        let files: Vec<_> = search(client, "px").into_iter().map(|v| v.1).collect();
+        assert_eq!(
+            files,
+            vec!["near/main.c", "near/other.c", "near/sub/x.c", "far/a/b/y.c"]
+        );
+    });
+}
+
+#[test]
+fn text_document_sync() {
+    common::workspace::run("text_document_sync", |client, root| {
+        let uri = common::workspace::uri(root, "test.c");
+        let on_disk = std::fs::read_to_string(format!("{}/test.c", root)).unwrap();
+        let outline = |client: &mut common::lsp_client::LspClient| -> Vec<String> {
+            let params = json!({ "textDocument": { "uri": uri } });
+            let rsp = client
+                .request("textDocument/documentSymbol", params)
+                .unwrap();
+            rsp.as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        // Opened buffer shadows the file on disk.
+        let text = format!("{}\nstatic int s_extra;\n", on_disk);
+        let params = json!({
+            "textDocument": { "uri": uri, "languageId": "c", "version": 1, "text": text },
+        });
+        client.notify("textDocument/didOpen", params).unwrap();
+        assert!(outline(client).contains(&"s_extra".to_string()));
+
+        // Changes are indexed without save.
+        let text = format!("{}\nvoid s_changed(void);\n", on_disk);
+        let params = json!({
+            "textDocument": { "uri": uri, "version": 2 },
+            "contentChanges": [ { "text": text } ],
+        });
+        client.notify("textDocument/didChange", params).unwrap();
+        let names = outline(client);
+        assert!(names.contains(&"s_changed".to_string()));
+        assert!(!names.contains(&"s_extra".to_string()));
+
+        let params = json!({
+            "textDocument": { "uri": uri },
+            "position": { "line": 65, "character": 8 },
+        });
+        let rsp = client.request("textDocument/definition", params).unwrap();
+        assert_eq!(
+            rsp[0]["range"]["start"],
+            json!({ "line": 65, "character": 5 })
+        );
+
+        // Closing drops unsaved changes.
+        let params = json!({ "textDocument": { "uri": uri } });
+        client.notify("textDocument/didClose", params).unwrap();
+        assert!(!outline(client).contains(&"s_changed".to_string()));
+    });
+}
+
+#[test]
+fn incremental_sync() {
+    common::workspace::run("incremental_sync", |client, root| {
+        let uri = common::workspace::uri(root, "test.c");
+        let mut text = std::fs::read_to_string(format!("{}/test.c", root)).unwrap();
+        let snapshot = |client: &mut common::lsp_client::LspClient| -> Vec<serde_json::Value> {
+            let mut ret = vec![client
+                .request(
+                    "textDocument/documentSymbol",
+                    json!({ "textDocument": { "uri": uri } }),
+                )
+                .unwrap()];
+            for (line, character) in [(4, 12), (24, 18), (64, 12)] {
+                let params = json!({
+                    "textDocument": { "uri": uri },
+                    "position": { "line": line, "character": character },
+                    "context": { "includeDeclaration": true },
+                });
+                ret.push(client.request("textDocument/references", params).unwrap());
+            }
+            ret
+        };
+
+        let params = json!({
+            "textDocument": { "uri": uri, "languageId": "c", "version": 1, "text": text },
+        });
+        client.notify("textDocument/didOpen", params).unwrap();
+
+        // Insert a line, replace a name, remove a field and split a line.
+        let edits = [
+            ((4, 0), (4, 0), "static int s_inc;\n"),
+            ((63, 11), (63, 15), "s_sub"),
+            ((20, 0), (21, 0), ""),
+            ((36, 4), (36, 10), "int i;\n    int j = s_inc;"),
+        ];
+        for (version, (beg, end, new_text)) in edits.iter().enumerate() {
+            let offset = |(line, character): (usize, usize)| -> usize {
+                text.split_inclusive('\n')
+                    .take(line)
+                    .map(|v| v.len())
+                    .sum::<usize>()
+                    + character
+            };
+            let range = offset(*beg)..offset(*end);
+            text.replace_range(range, new_text);
+
+            let params = json!({
+                "textDocument": { "uri": uri, "version": version + 2 },
+                "contentChanges": [ {
+                    "range": {
+                        "start": { "line": beg.0, "character": beg.1 },
+                        "end": { "line": end.0, "character": end.1 },
+                    },
+                    "text": new_text,
+                } ],
+            });
+            client.notify("textDocument/didChange", params).unwrap();
+        }
+        let incremental = snapshot(client);
+
+        // The same content with full sync gives the same result.
+        let params = json!({
+            "textDocument": { "uri": uri, "version": 10 },
+            "contentChanges": [ { "text": text } ],
+        });
+        client.notify("textDocument/didChange", params).unwrap();
+        let full = snapshot(client);
+
+        assert_eq!(incremental, full);
+        assert_eq!(full[1].as_array().unwrap().len(), 2);
+    });
+}
+
+#[test]
+fn background_indexing() {
+    common::workspace::run("background_indexing", |client, _| {
+        let messages = client.take_notifications();
+        let create = messages
+            .iter()
+            .find(|v| v["method"] == "window/workDoneProgress/create")
+            .unwrap();
+        let token = create["params"]["token"].clone();
+        assert!(token
+            .as_str()
+            .unwrap()
+            .starts_with("syntaxforest/indexing/"));
+
+        let progress: Vec<_> = messages
+            .iter()
+            .filter(|v| v["method"] == "$/progress")
+            .inspect(|v| assert_eq!(v["params"]["token"], token))
+            .map(|v| v["params"]["value"].clone())
+            .collect();
+        assert_eq!(progress.first().unwrap()["kind"], "begin");
+        assert_eq!(progress.first().unwrap()["title"], "Indexing");
+        assert_eq!(
+            progress[progress.len() - 2],
+            json!({ "kind": "report", "cancellable": false, "message": "2/2 files", "percentage": 100 })
+        );
+        assert_eq!(
+            progress.last().unwrap(),
+            &json!({ "kind": "end", "message": "2 files indexed" })
+        );
+
+        // A rescan creates a token of its own.
+        let settings = json!({ "settings": { "exclude": ["nothing"] } });
+        client
+            .notify("workspace/didChangeConfiguration", settings)
+            .unwrap();
+        client.wait_indexed().unwrap();
+        let creates: Vec<_> = client
+            .take_notifications()
+            .into_iter()
+            .filter(|v| v["method"] == "window/workDoneProgress/create")
+            .collect();
+        assert_eq!(creates.len(), 1);
+        assert_ne!(creates[0]["id"], create["id"]);
+        assert_ne!(creates[0]["params"]["token"], token);
+    });
+}
+
+#[test]
+fn parallel_indexing() {
+    const FILES: usize = 300;
+
+    let setup = |root: &str| {
+        std::fs::create_dir_all(format!("{}/gen", root)).unwrap();
+        for i in 0..FILES {
+            let content = format!("int gen_func_{}(void)\n{{\n    return {};\n}}\n", i, i);
+            std::fs::write(format!("{}/gen/gen_{}.c", root, i), content).unwrap();
+        }
+    };
+
+    common::workspace::run_with("parallel_indexing", setup, |client, _| {
+        let end = client
+            .take_notifications()
+            .into_iter()
+            .rfind(|v| v["method"] == "$/progress")
+            .unwrap();
+        assert_eq!(
+            end["params"]["value"]["message"],
+            format!("{} files indexed", FILES + 2)
+        );
+
+        for i in [0, FILES / 2, FILES - 1] {
+            let name = format!("gen_func_{}", i);
+            let rsp = client
+                .request("workspace/symbol", json!({ "query": name }))
+                .unwrap();
+            assert_eq!(rsp[0]["name"], name);
+            assert!(rsp[0]["location"]["uri"]
+                .as_str()
+                .unwrap()
+                .ends_with(&format!("/gen/gen_{}.c", i)));
+        }
+    });
+}
+
+/// Get the message of the last indexing progress.
+fn indexed(client: &mut common::lsp_client::LspClient) -> String {
+    let end = client
+        .take_notifications()
+        .into_iter()
+        .rfind(|v| v["method"] == "$/progress")
+        .unwrap();
+    end["params"]["value"]["message"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Count workspace symbols matching the query.
+fn find_symbol(client: &mut common::lsp_client::LspClient, name: &str) -> usize {
+    let rsp = client
+        .request("workspace/symbol", json!({ "query": name }))
+        .unwrap();
+    rsp.as_array().unwrap().len()
+}
+
+#[test]
+fn restart_reuses_index() {
+    let setup = |root: &str| {
+        let content = "int extra_func(void)\n{\n    return extra_func();\n}\n";
+        std::fs::write(format!("{}/extra.c", root), content).unwrap();
+    };
+    common::workspace::run_with("restart_reuses_index", setup, |client, _| {
+        assert_eq!(indexed(client), "3 files indexed");
+    });
+
+    // Nothing changed, nothing parsed.
+    let root = common::workspace::rerun("restart_reuses_index", |client, _| {
+        assert_eq!(indexed(client), "0 files indexed");
+        assert_eq!(find_symbol(client, "extra_func"), 1);
+    });
+
+    // Records of deleted files are removed.
+    std::fs::remove_file(format!("{}/extra.c", root)).unwrap();
+    common::workspace::rerun("restart_reuses_index", |client, _| {
+        assert_eq!(indexed(client), "0 files indexed");
+        assert_eq!(find_symbol(client, "extra_func"), 0);
+        assert_eq!(find_symbol(client, "_add"), 2);
+    });
+
+    let db = rusqlite::Connection::open(format!("{}/tags.db", root)).unwrap();
+    for table in ["files", "tags", "xrefs"] {
+        let sql = format!("SELECT COUNT(*) FROM {} WHERE path LIKE '%extra.c'", table);
+        let count: i64 = db.query_row(&sql, [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 0);
+    }
+}
+
+#[test]
+fn content_hash_detection() {
+    let setup = |root: &str| {
+        std::fs::write(format!("{}/extra.c", root), "int extra_aaa;\n").unwrap();
+    };
+    let root = common::workspace::run_with("content_hash_detection", setup, |client, _| {
+        assert_eq!(indexed(client), "3 files indexed");
+    });
+
+    // Touched without change, nothing parsed.
+    let file = std::fs::File::options()
+        .write(true)
+        .open(format!("{}/test.c", root))
+        .unwrap();
+    let mtime = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+    file.set_modified(mtime).unwrap();
+    common::workspace::rerun("content_hash_detection", |client, _| {
+        assert_eq!(indexed(client), "0 files indexed");
+    });
+
+    // Rewritten with the same size within the same second.
+    let path = format!("{}/extra.c", root);
+    let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+    std::fs::write(&path, "int extra_bbb;\n").unwrap();
+    let file = std::fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(mtime + std::time::Duration::from_nanos(1))
+        .unwrap();
+    common::workspace::rerun("content_hash_detection", |client, _| {
+        assert_eq!(indexed(client), "1 files indexed");
+        assert_eq!(find_symbol(client, "extra_aaa"), 0);
+        assert_eq!(find_symbol(client, "extra_bbb"), 1);
+    });
+}
+
+#[test]
+fn database_rebuild() {
+    fn check(client: &mut common::lsp_client::LspClient, root: &str) {
+        assert_eq!(indexed(client), "2 files indexed");
+        assert_eq!(find_symbol(client, "_add"), 2);
+
+        let db = rusqlite::Connection::open(format!("{}/tags.db", root)).unwrap();
+        let version: i64 = db
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, 2);
+    }
+
+    // Built before schema versioning.
+    let setup = |root: &str| {
+        let db = rusqlite::Connection::open(format!("{}/tags.db", root)).unwrap();
+        db.execute_batch(
+            "CREATE TABLE files (path TEXT PRIMARY KEY NOT NULL, mtime INTEGER, ptime INTEGER);
+            CREATE TABLE startup_scan_files (path TEXT PRIMARY KEY NOT NULL, mtime INTEGER);",
+        )
+        .unwrap();
+    };
+    let root = common::workspace::run_with("database_rebuild", setup, check);
+
+    // Built by a newer version.
+    let db = rusqlite::Connection::open(format!("{}/tags.db", root)).unwrap();
+    db.pragma_update(None, "user_version", 99).unwrap();
+    drop(db);
+    common::workspace::rerun("database_rebuild", check);
+
+    // Corrupt.
+    let mut content = b"SQLite format 3\0".to_vec();
+    content.resize(8192, 0x5a);
+    std::fs::write(format!("{}/tags.db", root), content).unwrap();
+    common::workspace::rerun("database_rebuild", check);
+
+    // Not a database, the server fails to start and the file is kept.
+    std::fs::write(format!("{}/tags.db", root), "notes\n").unwrap();
+    let config = syntax_forest::LspConfig {
+        dbfile: Some(format!("{}/tags.db", root)),
+        logdir: Some(root.clone()),
+        ..Default::default()
+    };
+    let (stream, server) = common::workspace::listen_tcp(config);
+    let mut stream = std::io::BufReader::new(stream);
+    let params = json!({ "processId": null, "rootUri": null, "capabilities": {} });
+    raw_request(&mut stream, 1, "initialize", params);
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+    );
+    assert!(server.join().is_err());
+    assert_eq!(
+        std::fs::read_to_string(format!("{}/tags.db", root)).unwrap(),
+        "notes\n"
+    );
+}
+
+#[test]
+fn database_locked() {
+    let dir = format!("{}/database_locked", env!("CARGO_TARGET_TMPDIR"));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // Another server is writing to the database.
+    let db = rusqlite::Connection::open(format!("{}/tags.db", dir)).unwrap();
+    db.execute_batch("CREATE TABLE other (id INTEGER); BEGIN EXCLUSIVE;")
+        .unwrap();
+
+    let config = syntax_forest::LspConfig {
+        dbfile: Some(format!("{}/tags.db", dir)),
+        logdir: Some(dir.clone()),
+        ..Default::default()
+    };
+    let (stream, server) = common::workspace::listen_tcp(config);
+    let mut stream = std::io::BufReader::new(stream);
+    let params = json!({ "processId": null, "rootUri": null, "capabilities": {} });
+    raw_request(&mut stream, 1, "initialize", params);
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+    );
+
+    // The server fails to start, and the database is kept.
+    assert!(server.join().is_err());
+    db.execute_batch("COMMIT;").unwrap();
+    let tables: i64 = db
+        .query_row("SELECT COUNT(*) FROM other", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(tables, 0);
+}
 
-    common::asset::Asset::cleanup_and_extract(cargo_target_tmpdir).unwrap();
+#[test]
+fn file_operations() {
+    common::workspace::run("file_operations", |client, root| {
+        let registration = client
+            .take_notifications()
+            .into_iter()
+            .find(|v| v["method"] == "client/registerCapability")
+            .unwrap();
+        assert_eq!(
+            registration["params"]["registrations"][0]["registerOptions"],
+            json!({ "watchers": [ { "globPattern": "**/*.c" }, { "globPattern": "**/*.h" } ] })
+        );
+
+        // Changes are indexed in background.
+        let location =
+            |client: &mut common::lsp_client::LspClient, name: &str, expected: &[String]| {
+                let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+                loop {
+                    let rsp = client
+                        .request("workspace/symbol", json!({ "query": name }))
+                        .unwrap();
+                    let uris: Vec<_> = rsp
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|v| v["location"]["uri"].as_str().unwrap().to_string())
+                        .collect();
+                    if uris == expected || std::time::Instant::now() > deadline {
+                        assert_eq!(uris, expected);
+                        return;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+            };
+
+        // Created.
+        std::fs::write(format!("{}/new.c", root), "int new_func(void);\n").unwrap();
+        let params = json!({ "files": [ { "uri": common::workspace::uri(root, "new.c") } ] });
+        client.notify("workspace/didCreateFiles", params).unwrap();
+        location(client, "new_func", &[common::workspace::uri(root, "new.c")]);
+
+        // Renamed.
+        std::fs::rename(format!("{}/new.c", root), format!("{}/moved.c", root)).unwrap();
+        let params = json!({ "files": [ {
+            "oldUri": common::workspace::uri(root, "new.c"),
+            "newUri": common::workspace::uri(root, "moved.c"),
+        } ] });
+        client.notify("workspace/didRenameFiles", params).unwrap();
+        location(
+            client,
+            "new_func",
+            &[common::workspace::uri(root, "moved.c")],
+        );
+
+        // Changed outside the editor.
+        std::fs::write(format!("{}/moved.c", root), "int changed_func(void);\n").unwrap();
+        let params = json!({ "changes": [ {
+            "uri": common::workspace::uri(root, "moved.c"), "type": 2,
+        } ] });
+        client
+            .notify("workspace/didChangeWatchedFiles", params)
+            .unwrap();
+        location(client, "new_func", &[]);
+        location(
+            client,
+            "changed_func",
+            &[common::workspace::uri(root, "moved.c")],
+        );
 
-    let mut client = common::lsp_client::LspClient::new().unwrap();
-    let port = client.local_addr().port();
+        // Deleted.
+        std::fs::remove_file(format!("{}/moved.c", root)).unwrap();
+        let params = json!({ "files": [ { "uri": common::workspace::uri(root, "moved.c") } ] });
+        client.notify("workspace/didDeleteFiles", params).unwrap();
+        location(client, "changed_func", &[]);
 
-    let mut client_copy = client.clone();
-    let thread_handle = std::thread::spawn(move || {
-        client_copy.initialize().unwrap();
-        client_copy.shutdown().unwrap();
+        // Directories.
+        std::fs::create_dir(format!("{}/sub", root)).unwrap();
+        std::fs::write(format!("{}/sub/a.c", root), "int sub_func(void);\n").unwrap();
+        let params = json!({ "files": [ { "uri": common::workspace::uri(root, "sub") } ] });
+        client.notify("workspace/didCreateFiles", params).unwrap();
+        location(
+            client,
+            "sub_func",
+            &[common::workspace::uri(root, "sub/a.c")],
+        );
+
+        std::fs::remove_dir_all(format!("{}/sub", root)).unwrap();
+        let params = json!({ "changes": [ {
+            "uri": common::workspace::uri(root, "sub"), "type": 3,
+        } ] });
+        client
+            .notify("workspace/didChangeWatchedFiles", params)
+            .unwrap();
+        location(client, "sub_func", &[]);
+
+        // New associations are watched in place of the old patterns.
+        client.take_notifications();
+        let params = json!({ "settings": { "associations": { "*.inc": "C" } } });
+        client
+            .notify("workspace/didChangeConfiguration", params)
+            .unwrap();
+        client
+            .wait_for(|v| v["method"] == "client/registerCapability")
+            .unwrap();
+        let messages = client.take_notifications();
+        let methods: Vec<_> = messages
+            .iter()
+            .filter_map(|v| v["method"].as_str())
+            .filter(|v| v.ends_with("registerCapability"))
+            .collect();
+        assert_eq!(
+            methods,
+            vec!["client/unregisterCapability", "client/registerCapability"]
+        );
+        let find = |method: &str| messages.iter().find(|v| v["method"] == method).unwrap();
+        let unregistration = find("client/unregisterCapability");
+        assert_eq!(
+            unregistration["params"]["unregisterations"][0]["id"],
+            registration["params"]["registrations"][0]["id"]
+        );
+        let registration = find("client/registerCapability");
+        assert_ne!(registration["id"], unregistration["id"]);
+        assert_eq!(
+            registration["params"]["registrations"][0]["registerOptions"],
+            json!({ "watchers": [
+                { "globPattern": "**/*.inc" },
+                { "globPattern": "**/*.c" },
+                { "globPattern": "**/*.h" },
+            ] })
+        );
     });
+}
 
-    // Start lsp server.
+/// Poll `workspace/symbol` until the number of matches satisfies `pred`.
+fn wait_symbol<F>(client: &mut common::lsp_client::LspClient, name: &str, pred: F) -> bool
+where
+    F: Fn(usize) -> bool,
+{
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while std::time::Instant::now() < deadline {
+        if pred(find_symbol(client, name)) {
+            return true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    false
+}
+
+#[test]
+fn file_watcher() {
+    common::workspace::run_with_options(
+        "file_watcher",
+        |_| {},
+        json!({ "watch": true }),
+        |client, root| {
+            // The server watches files itself, so it does not ask the client to.
+            assert!(client
+                .take_notifications()
+                .iter()
+                .all(|v| v["method"] != "client/registerCapability"));
+
+            // Ignored files are not indexed.
+            std::fs::write(format!("{}/.gitignore", root), "ignored/\n").unwrap();
+            std::fs::create_dir(format!("{}/ignored", root)).unwrap();
+            std::fs::write(format!("{}/ignored/a.c", root), "int ignored_func(void);\n").unwrap();
+
+            // Created.
+            std::fs::write(format!("{}/new.c", root), "int watched_func(void);\n").unwrap();
+            assert!(wait_symbol(client, "watched_func", |n| n == 1));
+            assert_eq!(find_symbol(client, "ignored_func"), 0);
+
+            // Exclude file of the repository applies too, once it changes.
+            std::fs::create_dir_all(format!("{}/.git/info", root)).unwrap();
+            std::fs::write(format!("{}/.git/info/exclude", root), "excluded.c\n").unwrap();
+            std::fs::write(format!("{}/excluded.c", root), "int excluded_func(void);\n").unwrap();
+            std::fs::write(format!("{}/kept.c", root), "int kept_func(void);\n").unwrap();
+            assert!(wait_symbol(client, "kept_func", |n| n == 1));
+            assert_eq!(find_symbol(client, "excluded_func"), 0);
+
+            // Changed.
+            std::fs::write(format!("{}/new.c", root), "int changed_func(void);\n").unwrap();
+            assert!(wait_symbol(client, "changed_func", |n| n == 1));
+            assert_eq!(find_symbol(client, "watched_func"), 0);
+
+            // Deleted.
+            std::fs::remove_file(format!("{}/new.c", root)).unwrap();
+            assert!(wait_symbol(client, "changed_func", |n| n == 0));
+        },
+    );
+}
+
+#[test]
+fn workspace_folders() {
+    let extra = format!("{}/workspace_folders_extra", env!("CARGO_TARGET_TMPDIR"));
+    std::fs::remove_dir_all(&extra).ok();
+    std::fs::create_dir_all(&extra).unwrap();
+    std::fs::write(format!("{}/extra.c", extra), "int extra_func(void);\n").unwrap();
+
+    common::workspace::run("workspace_folders", move |client, root| {
+        let folder = json!({
+            "uri": lsp_types::Url::from_file_path(&extra).unwrap().to_string(),
+            "name": "extra",
+        });
+
+        // Added folder is indexed in background.
+        client.take_notifications();
+        let params = json!({ "event": { "added": [ folder ], "removed": [] } });
+        client
+            .notify("workspace/didChangeWorkspaceFolders", params)
+            .unwrap();
+        client.wait_indexed().unwrap();
+        assert_eq!(indexed(client), "1 files indexed");
+
+        // Results are tagged with their folder.
+        let rsp = client
+            .request("workspace/symbol", json!({ "query": "extra_func" }))
+            .unwrap();
+        assert_eq!(rsp[0]["containerName"], "extra");
+        let rsp = client
+            .request("workspace/symbol", json!({ "query": "_add" }))
+            .unwrap();
+        let name = std::path::Path::new(root).file_name().unwrap();
+        assert_eq!(rsp[0]["containerName"], name.to_str().unwrap());
+
+        // Removed folder is purged, the others are kept.
+        let params = json!({ "event": { "added": [], "removed": [ folder ] } });
+        client
+            .notify("workspace/didChangeWorkspaceFolders", params)
+            .unwrap();
+        assert!(wait_symbol(client, "extra_func", |n| n == 0));
+        assert!(find_symbol(client, "_add") > 0);
+    });
+}
+
+#[test]
+fn error_handling() {
+    let setup = |root: &str| {
+        // Not valid UTF-8.
+        std::fs::write(format!("{}/bad.c", root), b"int bad_func(\xff\xfe);\n").unwrap();
+    };
+
+    common::workspace::run_with("error_handling", setup, |client, _root| {
+        // Invalid bytes are replaced, the bad file is indexed as well.
+        assert_eq!(find_symbol(client, "bad_func"), 1);
+        assert!(find_symbol(client, "_add") > 0);
+
+        // Malformed params are answered with an error, and the server keeps running.
+        let err = client
+            .request("textDocument/definition", json!({ "position": 1 }))
+            .unwrap_err();
+        let err: serde_json::Value = serde_json::from_str(&err.to_string()).unwrap();
+        assert_eq!(err["code"], -32602);
+
+        client
+            .notify("textDocument/didOpen", json!({ "textDocument": 1 }))
+            .unwrap();
+        assert!(find_symbol(client, "_add") > 0);
+    });
+}
+
+#[test]
+fn source_encoding() {
+    let setup = |root: &str| {
+        let write = |name: &str, content: &[u8]| {
+            let path = format!("{}/{}", root, name);
+            std::fs::create_dir_all(std::path::Path::new(&path).parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+
+        // Latin-1 by default, "café".
+        write("latin1.c", b"/* caf\xe9 */ int latin_func(void);\n");
+        // GBK by pattern, "中文".
+        write(
+            "legacy/gbk.c",
+            b"/* \xd6\xd0\xce\xc4 */ int gbk_func(void);\n",
+        );
+        // Marked by byte order mark.
+        write("bom8.c", b"\xef\xbb\xbfint bom8_func(void);\n");
+        let utf16: Vec<u8> = "\u{feff}/* 中文 */ int bom16_func(void);\n"
+            .encode_utf16()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        write("bom16.c", &utf16);
+    };
+    let options = json!({
+        "encoding": "latin1",
+        "encodingOverrides": { "legacy/**": "gbk" },
+    });
+
+    common::workspace::run_with_options("source_encoding", setup, options, |client, _root| {
+        // Columns are counted in the decoded text.
+        for (name, character) in [
+            ("latin_func", 16),
+            ("gbk_func", 17),
+            ("bom8_func", 4),
+            ("bom16_func", 17),
+        ] {
+            let rsp = client
+                .request("workspace/symbol", json!({ "query": name }))
+                .unwrap();
+            let symbol = rsp
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|v| v["name"] == name)
+                .unwrap_or_else(|| panic!("{} not found", name));
+            assert_eq!(
+                symbol["location"]["range"]["start"]["character"], character,
+                "{}",
+                name
+            );
+        }
+
+        // Text of files on disk is decoded as well.
+        let rsp = client
+            .request("workspace/symbol", json!({ "query": "gbk_func" }))
+            .unwrap();
+        let uri = rsp[0]["location"]["uri"].clone();
+        let rsp = client
+            .request(
+                "textDocument/definition",
+                json!({ "textDocument": { "uri": uri }, "position": { "line": 0, "character": 18 } }),
+            )
+            .unwrap();
+        assert_eq!(rsp[0]["range"]["start"]["character"], 17);
+    });
+}
+
+#[test]
+fn crash_quarantine() {
+    let root = common::workspace::run("crash_quarantine", |client, _root| {
+        assert_eq!(indexed(client), "2 files indexed");
+    });
+
+    // Crashed the indexer too many times.
+    let quarantine = |root: &str| {
+        let db = rusqlite::Connection::open(format!("{}/tags.db", root)).unwrap();
+        db.execute("UPDATE files SET ptime = 0, crashes = 3", ())
+            .unwrap();
+    };
+    quarantine(&root);
+    common::workspace::rerun("crash_quarantine", |client, _root| {
+        assert_eq!(indexed(client), "0 files indexed");
+    });
+
+    // Parsed again once changed.
+    let path = format!("{}/test.c", root);
+    let mut content = std::fs::read_to_string(&path).unwrap();
+    content.push('\n');
+    std::fs::write(&path, content).unwrap();
+    common::workspace::rerun("crash_quarantine", |client, _root| {
+        assert_eq!(indexed(client), "1 files indexed");
+    });
+}
+
+#[test]
+fn request_cancellation() {
+    common::workspace::run("request_cancellation", |client, root| {
+        let uri = common::workspace::uri(root, "test.c");
+        let text = std::fs::read_to_string(format!("{}/test.c", root)).unwrap();
+        let params = json!({ "textDocument": {
+            "uri": uri, "languageId": "c", "version": 1, "text": text,
+        } });
+        client.notify("textDocument/didOpen", params).unwrap();
+
+        let references = json!({
+            "textDocument": { "uri": uri },
+            "position": { "line": 62, "character": 12 },
+            "context": { "includeDeclaration": true },
+        });
+
+        // Canceled requests are answered, either with result or with cancellation.
+        let ids: Vec<_> = (0..64)
+            .map(|_| {
+                client
+                    .send_request("textDocument/references", references.clone())
+                    .unwrap()
+            })
+            .collect();
+        for id in &ids {
+            client
+                .notify("$/cancelRequest", json!({ "id": id }))
+                .unwrap();
+        }
+        for id in ids {
+            let rsp = client.recv_response(id).unwrap();
+            assert!(rsp.get("result").is_some() || rsp["error"]["code"] == -32800);
+        }
+
+        // Requests running over an edit never answer with stale results.
+        let lines = |v: &serde_json::Value| {
+            let mut lines: Vec<_> = v
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v["range"]["start"]["line"].as_u64().unwrap())
+                .collect();
+            lines.sort();
+            lines
+        };
+        let before = lines(
+            &client
+                .request("textDocument/references", references.clone())
+                .unwrap(),
+        );
+        let after: Vec<_> = before.iter().map(|v| v + 1).collect();
+        assert!(before.len() >= 2);
+
+        let ids: Vec<_> = (0..64)
+            .map(|_| {
+                client
+                    .send_request("textDocument/references", references.clone())
+                    .unwrap()
+            })
+            .collect();
+        let params = json!({
+            "textDocument": { "uri": uri, "version": 2 },
+            "contentChanges": [ {
+                "range": { "start": { "line": 0, "character": 0 },
+                           "end": { "line": 0, "character": 0 } },
+                "text": "\n",
+            } ],
+        });
+        client.notify("textDocument/didChange", params).unwrap();
+
+        // Invalid settings are reported as soon as they are handled, which is after the
+        // edit is applied. Only responses sent before that may come from old content.
+        let params = json!({ "settings": { "threads": "many" } });
+        client
+            .notify("workspace/didChangeConfiguration", params)
+            .unwrap();
+        let early = client
+            .responses_until(|v| v["method"] == "window/showMessage")
+            .unwrap();
+        for rsp in &early {
+            match rsp.get("result") {
+                Some(v) => assert!(lines(v) == before || lines(v) == after),
+                None => assert_eq!(rsp["error"]["code"], -32801),
+            }
+        }
+        for id in ids {
+            if early.iter().any(|v| v["id"] == id) {
+                continue;
+            }
+            let rsp = client.recv_response(id).unwrap();
+            match rsp.get("result") {
+                Some(v) => assert_eq!(lines(v), after),
+                None => assert_eq!(rsp["error"]["code"], -32801),
+            }
+        }
+
+        // Later requests see the edit.
+        let params = json!({
+            "textDocument": { "uri": uri },
+            "position": { "line": 63, "character": 12 },
+            "context": { "includeDeclaration": true },
+        });
+        let rsp = client.request("textDocument/references", params).unwrap();
+        assert!(rsp.as_array().unwrap().len() >= 2);
+    });
+}
+
+/// Send a message over a raw stream.
+fn raw_send<S: std::io::Write>(stream: &mut S, msg: serde_json::Value) {
+    let payload = msg.to_string();
+    let data = format!("Content-Length: {}\r\n\r\n{}", payload.len(), payload);
+    stream.write_all(data.as_bytes()).unwrap();
+}
+
+/// Receive a message from a raw stream.
+fn raw_recv<S: std::io::BufRead>(stream: &mut S) -> serde_json::Value {
+    let mut len = 0;
+    loop {
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        match line.trim_end() {
+            "" => break,
+            v => {
+                if let Some(v) = v.strip_prefix("Content-Length: ") {
+                    len = v.parse().unwrap();
+                }
+            }
+        }
+    }
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).unwrap();
+    serde_json::from_slice(&payload).unwrap()
+}
+
+/// Send a request over a raw stream and wait for its response, other messages are skipped.
+fn raw_request<S: std::io::Read + std::io::Write>(
+    stream: &mut std::io::BufReader<S>,
+    id: u32,
+    method: &str,
+    params: serde_json::Value,
+) -> serde_json::Value {
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }),
+    );
+    loop {
+        let msg = raw_recv(stream);
+        if msg["id"] == id && msg.get("method").is_none() {
+            return msg;
+        }
+    }
+}
+
+/// Initialize and shut down a server over a raw stream.
+fn handshake<S: std::io::Read + std::io::Write>(stream: S) {
+    let mut stream = std::io::BufReader::new(stream);
+
+    let params = json!({ "processId": null, "rootUri": null, "capabilities": {} });
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": params }),
+    );
+    let rsp = raw_recv(&mut stream);
+    assert!(rsp["result"]["capabilities"].is_object());
+
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+    );
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }),
+    );
+    assert_eq!(raw_recv(&mut stream)["id"], 2);
+
+    // Only exit is expected now, requests are refused and notifications dropped.
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "method": "workspace/didChangeConfiguration", "params": { "settings": null } }),
+    );
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "id": 3, "method": "workspace/symbol", "params": { "query": "x" } }),
+    );
+    let rsp = raw_recv(&mut stream);
+    assert_eq!(rsp["id"], 3);
+    assert_eq!(rsp["error"]["code"], -32600);
+
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    );
+}
+
+#[test]
+fn listen_transport() {
+    let dir = format!("{}/listen_transport", env!("CARGO_TARGET_TMPDIR"));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = syntax_forest::LspConfig {
+        dbfile: Some(format!("{}/tags.db", dir)),
+        logdir: Some(dir.clone()),
+        ..Default::default()
+    };
+
+    // TCP port.
+    let (stream, server) = common::workspace::listen_tcp(config.clone());
+    handshake(stream);
+    server.join().unwrap();
+
+    // Unix domain socket, the socket file is removed once connected.
+    #[cfg(unix)]
+    {
+        let path = format!("{}/lsp.sock", dir);
+        let unix = syntax_forest::LspConfig {
+            listen: Some(path.clone()),
+            ..config.clone()
+        };
+        let server = std::thread::spawn(move || syntax_forest::start_lsp(&unix).unwrap());
+        let stream = loop {
+            match std::os::unix::net::UnixStream::connect(&path) {
+                Ok(v) => break v,
+                Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        };
+        handshake(stream);
+        server.join().unwrap();
+        assert!(!std::path::Path::new(&path).exists());
+
+        // Connect to a socket created by client.
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let pipe = syntax_forest::LspConfig {
+            pipe: Some(path.clone()),
+            ..config.clone()
+        };
+        let server = std::thread::spawn(move || syntax_forest::start_lsp(&pipe).unwrap());
+        handshake(listener.accept().unwrap().0);
+        server.join().unwrap();
+
+        // A socket still in use is kept, a stale one is replaced.
+        let unix = syntax_forest::LspConfig {
+            listen: Some(path.clone()),
+            ..config
+        };
+        assert!(syntax_forest::start_lsp(&unix).is_err());
+        assert!(std::path::Path::new(&path).exists());
+        drop(listener);
+        let server = std::thread::spawn(move || syntax_forest::start_lsp(&unix).unwrap());
+        let stream = loop {
+            match std::os::unix::net::UnixStream::connect(&path) {
+                Ok(v) => break v,
+                Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        };
+        handshake(stream);
+        server.join().unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn client_process_exit() {
+    let dir = format!("{}/client_process_exit", env!("CARGO_TARGET_TMPDIR"));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(format!("{}/src", dir)).unwrap();
+    for i in 0..100 {
+        let content = format!("int exit_func_{}(void) {{ return {}; }}\n", i, i);
+        std::fs::write(format!("{}/src/{}.c", dir, i), content).unwrap();
+    }
+
+    let mut editor = std::process::Command::new("sleep")
+        .arg("60")
+        .spawn()
+        .unwrap();
+    let config = syntax_forest::LspConfig {
+        dbfile: Some(format!("{}/tags.db", dir)),
+        logdir: Some(dir.clone()),
+        client_process_id: Some(editor.id()),
+        ..Default::default()
+    };
+    let (stream, server) = common::workspace::listen_tcp(config);
+    let mut stream = std::io::BufReader::new(stream);
+    let params = json!({
+        "processId": null,
+        "rootUri": lsp_types::Url::from_file_path(format!("{}/src", dir)).unwrap().to_string(),
+        "capabilities": {},
+    });
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": params }),
+    );
+    raw_recv(&mut stream);
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+    );
+
+    // The editor crashes without shutdown, the server exits by itself. On Linux it does
+    // not wait for the editor to be reaped.
+    editor.kill().unwrap();
+    #[cfg(not(target_os = "linux"))]
+    editor.wait().unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while !server.is_finished() {
+        assert!(std::time::Instant::now() < deadline);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    server.join().unwrap();
+    #[cfg(target_os = "linux")]
+    editor.wait().unwrap();
+
+    // Indexed files are saved.
+    let db = rusqlite::Connection::open(format!("{}/tags.db", dir)).unwrap();
+    let parsed: i64 = db
+        .query_row(
+            "SELECT COUNT(*) FROM files WHERE ptime = mtime",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let tags: i64 = db
+        .query_row("SELECT COUNT(*) FROM tags", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(tags, parsed);
+}
+
+#[test]
+fn position_encoding() {
+    let dir = format!("{}/position_encoding", env!("CARGO_TARGET_TMPDIR"));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = format!("{}/utf16.c", dir);
+    let uri = lsp_types::Url::from_file_path(&path).unwrap().to_string();
+    let text = "/* 中文注释 */ int utf16_func(void);\n/* 𝄞 */ int utf16_call(void) { return utf16_func(); }\n";
+    std::fs::write(&path, text).unwrap();
+
+    // Returns the negotiated encoding, and the column of `utf16_func` before and after an edit.
+    let root = dir.clone();
+    let session = move |encodings: serde_json::Value| {
+        let config = syntax_forest::LspConfig {
+            dbfile: Some(format!("{}/tags.db", dir)),
+            logdir: Some(dir.clone()),
+            ..Default::default()
+        };
+        let (stream, server) = common::workspace::listen_tcp(config);
+        let mut stream = std::io::BufReader::new(stream);
+
+        let params = json!({
+            "processId": null,
+            "rootUri": lsp_types::Url::from_file_path(&dir).unwrap().to_string(),
+            "capabilities": {
+                "general": { "positionEncodings": encodings },
+                "textDocument": { "documentSymbol": { "hierarchicalDocumentSymbolSupport": true } },
+            },
+        });
+        let rsp = raw_request(&mut stream, 1, "initialize", params);
+        let encoding = rsp["result"]["capabilities"]["positionEncoding"].clone();
+        raw_send(
+            stream.get_mut(),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        );
+        raw_send(
+            stream.get_mut(),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": { "uri": uri, "languageId": "c", "version": 1, "text": text },
+            }}),
+        );
+
+        // Jump from the call after a surrogate pair to the prototype after Chinese text.
+        let character = match encoding.as_str() {
+            Some("utf-8") => 41,
+            Some("utf-16") => 39,
+            _ => 38,
+        };
+        let rsp = raw_request(
+            &mut stream,
+            2,
+            "textDocument/definition",
+            json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": 1, "character": character },
+            }),
+        );
+        let before = rsp["result"][0]["range"]["start"]["character"].clone();
+
+        // Insert text right after the comment.
+        let character = match encoding.as_str() {
+            Some("utf-8") => 19,
+            _ => 11,
+        };
+        raw_send(
+            stream.get_mut(),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [{
+                    "range": {
+                        "start": { "line": 0, "character": character },
+                        "end": { "line": 0, "character": character },
+                    },
+                    "text": "extern ",
+                }],
+            }}),
+        );
+        let rsp = raw_request(
+            &mut stream,
+            3,
+            "textDocument/documentSymbol",
+            json!({ "textDocument": { "uri": uri } }),
+        );
+        let after = rsp["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|v| v["name"] == "utf16_func")
+            .map(|v| v["selectionRange"]["start"]["character"].clone());
+
+        raw_request(&mut stream, 4, "shutdown", serde_json::Value::Null);
+        raw_send(
+            stream.get_mut(),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        );
+        server.join().unwrap();
+
+        (encoding, before, after)
+    };
+
+    // UTF-16 is the default.
+    let (encoding, before, _) = session(serde_json::Value::Null);
+    assert_eq!(encoding, "utf-16");
+    assert_eq!(before, 15);
+
+    // The first encoding the client prefers wins.
+    let (encoding, before, after) = session(json!(["utf-32", "utf-8"]));
+    assert_eq!(encoding, "utf-32");
+    assert_eq!(before, 15);
+    assert_eq!(after, Some(json!(22)));
+
+    let (encoding, before, after) = session(json!(["utf-16"]));
+    assert_eq!(encoding, "utf-16");
+    assert_eq!(before, 15);
+    assert_eq!(after, Some(json!(22)));
+
+    let (encoding, before, after) = session(json!(["utf-8"]));
+    assert_eq!(encoding, "utf-8");
+    assert_eq!(before, 23);
+    assert_eq!(after, Some(json!(30)));
+
+    // A file shorter than indexed, its positions point to its end.
+    let short = format!("{}/short.c", root);
+    std::fs::write(&short, "/* 中文 */\nint short_func(void);\n").unwrap();
     let config = syntax_forest::LspConfig {
-        dbfile: Some(dbfile_path),
-        port: Some(port),
-        logdir: Some(cargo_target_tmpdir.to_string()),
-        loglevel: Some("TRACE".into()),
+        dbfile: Some(format!("{}/tags.db", root)),
+        logdir: Some(root.clone()),
         ..Default::default()
     };
-    syntax_forest::start_lsp(&config).unwrap();
+    let (stream, server) = common::workspace::listen_tcp(config);
+    let mut stream = std::io::BufReader::new(stream);
+    let params = json!({
+        "processId": null,
+        "rootUri": lsp_types::Url::from_file_path(&root).unwrap().to_string(),
+        "capabilities": { "general": { "positionEncodings": ["utf-16"] } },
+    });
+    raw_request(&mut stream, 1, "initialize", params);
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+    );
+    let mut id = 2;
+    let mut search = |stream: &mut std::io::BufReader<std::net::TcpStream>| {
+        id += 1;
+        raw_request(
+            stream,
+            id,
+            "workspace/symbol",
+            json!({ "query": "short_func" }),
+        )
+    };
+    let start = std::time::Instant::now();
+    while search(&mut stream)["result"].as_array().unwrap().is_empty() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    std::fs::write(&short, "/* 中文 */").unwrap();
+    let rsp = search(&mut stream);
+    assert_eq!(
+        rsp["result"][0]["location"]["range"]["start"],
+        json!({ "line": 0, "character": 8 })
+    );
+
+    raw_request(&mut stream, 100, "shutdown", serde_json::Value::Null);
+    raw_send(
+        stream.get_mut(),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    );
+    server.join().unwrap();
+}
+
+#[test]
+fn settings() {
+    let setup = |root: &str| {
+        let toml = concat!(
+            "exclude = [\"vendor/**\"]\n",
+            "includePaths = [\"include\"]\n",
+            "maxFileSize = 1000\n",
+            "database = \"notes.txt\"\n",
+            "\n",
+            "[associations]\n",
+            "\".inc\" = \"C\"\n",
+        );
+        std::fs::write(format!("{}/.syntaxforest.toml", root), toml).unwrap();
+        std::fs::write(format!("{}/notes.txt", root), "notes\n").unwrap();
+
+        std::fs::create_dir_all(format!("{}/vendor", root)).unwrap();
+        std::fs::write(format!("{}/vendor/v.c", root), "int vendor_func(void);\n").unwrap();
+        std::fs::create_dir_all(format!("{}/include", root)).unwrap();
+        std::fs::write(format!("{}/include/ext.h", root), "int ext_func(void);\n").unwrap();
+        std::fs::write(format!("{}/extra.inc", root), "int inc_func(void);\n").unwrap();
+        let content = format!(
+            "#include \"ext.h\"\nint big_func(void);\n{}",
+            " ".repeat(2000)
+        );
+        std::fs::write(format!("{}/big.c", root), content).unwrap();
+    };
+
+    // Invalid initialization options are reported and ignored.
+    let options = json!({ "threads": "many" });
+    common::workspace::run_with_options("settings", setup, options, |client, root| {
+        let errors: Vec<_> = client
+            .take_notifications()
+            .into_iter()
+            .filter(|v| v["method"] == "window/showMessage")
+            .collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0]["params"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("database"));
+        assert!(errors[1]["params"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("many"));
+        assert_eq!(
+            std::fs::read_to_string(format!("{}/notes.txt", root)).unwrap(),
+            "notes\n"
+        );
+
+        // The workspace file applies.
+        assert_eq!(find_symbol(client, "vendor_func"), 0);
+        assert_eq!(find_symbol(client, "big_func"), 0);
+        assert_eq!(find_symbol(client, "inc_func"), 1);
+
+        // Include paths are searched.
+        let params = json!({
+            "textDocument": { "uri": common::workspace::uri(root, "big.c") },
+            "position": { "line": 0, "character": 12 },
+        });
+        let rsp = client.request("textDocument/definition", params).unwrap();
+        assert_eq!(rsp[0]["uri"], common::workspace::uri(root, "include/ext.h"));
+
+        // Client settings override the workspace file.
+        let settings = json!({ "syntaxforest": { "exclude": [], "maxFileSize": 100000 } });
+        client
+            .notify(
+                "workspace/didChangeConfiguration",
+                json!({ "settings": settings }),
+            )
+            .unwrap();
+        assert!(wait_symbol(client, "vendor_func", |n| n == 1));
+        assert!(wait_symbol(client, "big_func", |n| n == 1));
+
+        // Replacing them restores the workspace file, invalid values are reported.
+        client
+            .notify(
+                "workspace/didChangeConfiguration",
+                json!({ "settings": { "encoding": "nope" } }),
+            )
+            .unwrap();
+        client
+            .wait_for(|v| {
+                v["method"] == "window/showMessage"
+                    && v["params"]["message"].as_str().unwrap().contains("nope")
+            })
+            .unwrap();
+        assert!(wait_symbol(client, "vendor_func", |n| n == 0));
+        assert!(wait_symbol(client, "big_func", |n| n == 0));
+    });
+}
+
+#[test]
+fn language_detection() {
+    let setup = |root: &str| {
+        let files = [
+            // C++ headers are not parsed as C.
+            ("cpp.h", "namespace ns {\nclass Widget;\n}\nint cpp_func(void);\n"),
+            (
+                "guarded.h",
+                "#ifdef __cplusplus\nextern \"C\" {\n#endif\nint guarded_func(void);\n#ifdef __cplusplus\n}\n#endif\n",
+            ),
+            // Shebangs and modelines.
+            ("script", "#!/usr/bin/env tcc -run\nint script_func(void);\n"),
+            ("emacs", "/* -*- mode: C -*- */\nint emacs_func(void);\n"),
+            ("vim.c", "int vim_func(void);\n// vim: set ft=cpp:\n"),
+            ("notes", "int notes_func(void);\n"),
+            // Patterns match whole names, not suffixes.
+            ("abc", "int abc_func(void);\n"),
+            ("foo.abc", "int foo_abc_func(void);\n"),
+        ];
+        for (name, content) in files {
+            std::fs::write(format!("{}/{}", root, name), content).unwrap();
+        }
+    };
+
+    let options = json!({ "associations": { "abc": "C" } });
+    common::workspace::run_with_options("language_detection", setup, options, |client, root| {
+        assert_eq!(find_symbol(client, "cpp_func"), 0);
+        assert_eq!(find_symbol(client, "guarded_func"), 1);
+        assert_eq!(find_symbol(client, "script_func"), 1);
+        assert_eq!(find_symbol(client, "emacs_func"), 1);
+        assert_eq!(find_symbol(client, "vim_func"), 0);
+        assert_eq!(find_symbol(client, "notes_func"), 0);
+        assert_eq!(find_symbol(client, "abc_func"), 1);
+        assert_eq!(find_symbol(client, "foo_abc_func"), 0);
 
-    thread_handle.join().unwrap();
-    client.close().unwrap();
+        // The language ID of an opened document wins.
+        let open = |client: &mut common::lsp_client::LspClient, name: &str, language_id: &str| {
+            let path = format!("{}/{}", root, name);
+            let params = json!({
+                "textDocument": {
+                    "uri": common::workspace::uri(root, name),
+                    "languageId": language_id,
+                    "version": 1,
+                    "text": std::fs::read_to_string(path).unwrap(),
+                }
+            });
+            client.notify("textDocument/didOpen", params).unwrap();
+        };
+        open(client, "cpp.h", "c");
+        assert!(wait_symbol(client, "cpp_func", |n| n == 1));
+        open(client, "guarded.h", "cpp");
+        assert!(wait_symbol(client, "guarded_func", |n| n == 0));
+
+        // Detected again once closed.
+        let params =
+            json!({ "textDocument": { "uri": common::workspace::uri(root, "guarded.h") } });
+        client.notify("textDocument/didClose", params).unwrap();
+        assert!(wait_symbol(client, "guarded_func", |n| n == 1));
+    });
 }
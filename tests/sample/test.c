@@ -1,6 +1,7 @@
 #include <stdio.h>
 #include <errno.h>
 #include <stdlib.h>
+#include "test.h"
 
 #define ERROR_TABLE(xx) \
     xx(EINVAL, "Invalid argument")  \
@@ -8,6 +9,7 @@
 
 typedef enum test_errno
 {
+    TEST_OK = 0,
 #define EXPAND_ERROR(x, y)  TEST_##x = -x,
 ERROR_TABLE(EXPAND_ERROR)
 #undef EXPAND_ERROR
@@ -22,6 +24,8 @@ static runtime_t s_rt = { 0 };
 
 static const char* s_help = "Add arguments and return the result.";
 
+static int _add(int argc, char* argv[]);
+
 /**
  * @brief Add all arguments from command line.
  * @param[in] argc The number of arguments.
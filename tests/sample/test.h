@@ -0,0 +1,6 @@
+#ifndef TEST_H
+#define TEST_H
+
+#define TEST_VERSION 1
+
+#endif